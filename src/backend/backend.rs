@@ -1,13 +1,29 @@
+use std::collections::{HashMap, HashSet};
+
 use tokio::{
     select,
     sync::mpsc::{self, Receiver, Sender},
+    task::JoinHandle,
 };
 
-use crate::messages::{client_message::ClientMessage, voice_message::VoiceMessage};
+use crate::{
+    messages::{
+        client_error::ClientErrorKind, client_message::ClientMessage,
+        connection_event::ConnectionEvent, voice_message::VoiceMessage,
+    },
+    settings::{
+        certificate_mode::{CertificateMismatch, CertificateMode},
+        voice_settings::VoiceSettings,
+    },
+};
 
 use super::{
-    server_connection::ConnectionYawperClient,
-    voice_channel::{voice_input::VoiceInput, voice_output::VoiceOutput},
+    server_connection::{ConnectionParameters, ConnectionYawperClient},
+    voice_channel::{
+        audio_devices::list_audio_devices,
+        voice_input::{VoiceInput, VoiceSink},
+        voice_output::VoiceOutput,
+    },
 };
 
 pub struct BackendYawperClient {
@@ -15,8 +31,27 @@ pub struct BackendYawperClient {
     gui_commands_transmitter: Sender<ClientMessage>,
     server_connection: Option<ConnectionYawperClient>,
     server_connection_is_active: bool,
+    connection_events_transmitter: Sender<ConnectionEvent>,
+    connection_events_receiver: Receiver<ConnectionEvent>,
+    reconnect_task: Option<JoinHandle<()>>,
+    session: Option<ConnectionParameters>,
+    active_room: Option<(String, String)>,
     voice_input_control_transmitter: Option<Sender<VoiceMessage>>,
     voice_output_control_transmitter: Option<Sender<VoiceMessage>>,
+    input_device_id: Option<String>,
+    output_device_id: Option<String>,
+    voice_settings: VoiceSettings,
+    self_muted: bool,
+    self_deafened: bool,
+    level_meter_enabled: bool,
+    mic_loopback: bool,
+    mic_test_control_transmitter: Option<Sender<VoiceMessage>>,
+    /// Controls the loopback output of the mic test, so volume changes are heard right away.
+    mic_test_output_control_transmitter: Option<Sender<VoiceMessage>>,
+    /// Volumes and local mutes set for room members, handed to every voice output that gets
+    /// opened.
+    voice_volumes: HashMap<u64, f32>,
+    muted_voices: HashSet<u64>,
 }
 
 impl BackendYawperClient {
@@ -24,51 +59,100 @@ impl BackendYawperClient {
         backend_commands_receiver: Receiver<ClientMessage>,
         gui_commands_transmitter: Sender<ClientMessage>,
     ) -> Self {
+        let (connection_events_transmitter, connection_events_receiver) =
+            mpsc::channel::<ConnectionEvent>(10);
         Self {
             backend_commands_receiver,
             gui_commands_transmitter,
             server_connection: None,
             server_connection_is_active: false,
+            connection_events_transmitter,
+            connection_events_receiver,
+            reconnect_task: None,
+            session: None,
+            active_room: None,
             voice_input_control_transmitter: None,
             voice_output_control_transmitter: None,
+            input_device_id: None,
+            output_device_id: None,
+            voice_settings: VoiceSettings::default(),
+            self_muted: false,
+            self_deafened: false,
+            level_meter_enabled: false,
+            mic_loopback: false,
+            mic_test_control_transmitter: None,
+            mic_test_output_control_transmitter: None,
+            voice_volumes: HashMap::new(),
+            muted_voices: HashSet::new(),
         }
     }
 
-    pub async fn run(self: &mut Self) {
+    pub async fn run(&mut self) {
         loop {
             select! {
-                Some(message) = self.backend_commands_receiver.recv() => {
-                    self.process_gui_commands(message).await;
+                message = self.backend_commands_receiver.recv() => {
+                    match message {
+                        Some(message) => self.process_gui_commands(message).await,
+                        None => break,
+                    }
                 }
-                else => {
-                    break;
+                Some(event) = self.connection_events_receiver.recv() => {
+                    self.process_connection_events(event).await;
                 }
             }
         }
     }
 
-    pub async fn process_gui_commands(self: &mut Self, message: ClientMessage) {
+    pub async fn process_gui_commands(&mut self, message: ClientMessage) {
         match message {
             ClientMessage::ConnectToServer {
                 host_name,
                 host_password,
-            } => {
-                if !self.server_connection_is_active {
-                    match ConnectionYawperClient::new(host_name, host_password).await {
-                        Ok(new_server_connection) => {
-                            self.server_connection = Some(new_server_connection);
-                            self.server_connection_is_active = true;
+                certificate_mode,
+                client_id,
+                display_name,
+                color,
+            } if !self.server_connection_is_active && self.reconnect_task.is_none() => {
+                let mut parameters = ConnectionParameters {
+                    host_name,
+                    host_password,
+                    certificate_mode,
+                    client_id,
+                    display_name,
+                    color,
+                };
+                match ConnectionYawperClient::new(&parameters).await {
+                    Ok(new_server_connection) => {
+                        if let CertificateMode::TrustOnFirstUse { fingerprint } =
+                            &mut parameters.certificate_mode
+                            && fingerprint.is_none()
+                            && let Some(found) = &new_server_connection.certificate_fingerprint
+                        {
+                            *fingerprint = Some(found.clone());
                             let _ = self
                                 .gui_commands_transmitter
-                                .send(ClientMessage::ConnectionIsActive {})
+                                .send(ClientMessage::CertificateTrusted {
+                                    host_name: parameters.host_name.clone(),
+                                    fingerprint: found.clone(),
+                                })
                                 .await;
-                            if let Some(conn) = &self.server_connection {
-                                conn.start_updates(self.gui_commands_transmitter.clone());
-                            }
                         }
-                        Err(err) => {
-                            // TODO: provide this error to the gui
-                            println!("{}", err)
+                        self.session = Some(parameters);
+                        self.activate_connection(new_server_connection).await;
+                    }
+                    Err(err) => {
+                        if let Some(mismatch) = err.downcast_ref::<CertificateMismatch>() {
+                            let _ = self
+                                .gui_commands_transmitter
+                                .send(ClientMessage::CertificateChanged {
+                                    host_name: parameters.host_name,
+                                    expected: mismatch.expected.clone(),
+                                    found: mismatch.found.clone(),
+                                })
+                                .await;
+                        } else {
+                            self.report_error(ClientErrorKind::Connection, err.to_string())
+                                .await;
                         }
                     }
                 }
@@ -88,7 +172,10 @@ impl BackendYawperClient {
                         .await
                     {
                         Ok(_) => {}
-                        Err(err) => println!("Error during room creation: {}", err),
+                        Err(err) => {
+                            self.report_error(ClientErrorKind::RoomCreation, err.to_string())
+                                .await;
+                        }
                     }
                 }
             }
@@ -96,75 +183,513 @@ impl BackendYawperClient {
                 room_name,
                 room_password,
             } => {
-                if self.server_connection_is_active
+                self.join_room(room_name, room_password).await;
+            }
+            ClientMessage::SendChatMessage { body } => {
+                if self.active_room.is_some()
                     && let Some(conn) = &self.server_connection
-                {
-                    match conn
-                        .send_command(ClientMessage::JoinRoom {
-                            room_name: room_name.clone(),
-                            room_password,
-                        })
+                    && let Err(err) = conn
+                        .send_command(ClientMessage::SendChatMessage { body })
                         .await
-                    {
-                        Ok(_) => {
-                            let _ = self
-                                .gui_commands_transmitter
-                                .send(ClientMessage::RoomJoined { room_name })
-                                .await;
-
-                            let (voice_input_control_transmitter, voice_input_control_receiver) =
-                                mpsc::channel::<VoiceMessage>(100);
-                            let connection_clone = conn.connection.clone();
-                            match VoiceInput::new(voice_input_control_receiver, connection_clone) {
-                                Ok(voice_input) => match voice_input.run() {
-                                    Ok(_) => {
-                                        self.voice_input_control_transmitter =
-                                            Some(voice_input_control_transmitter);
-                                    }
-                                    Err(err) => {
-                                        println!(
-                                            "Error during voice input stream creation: {}",
-                                            err
-                                        )
-                                    }
-                                },
-                                Err(err) => println!("Error during voice input creation: {}", err),
-                            }
-                            let (voice_output_control_transmitter, voice_output_control_receiver) =
-                                mpsc::channel::<VoiceMessage>(100);
-                            let mut voice_output_opt = None;
-                            match VoiceOutput::new(voice_output_control_receiver) {
-                                Ok(voice_output) => {
-                                    voice_output_opt = Some(voice_output);
-                                    self.voice_output_control_transmitter =
-                                        Some(voice_output_control_transmitter);
-                                }
-                                Err(err) => println!("Error during voice output creation: {}", err),
-                            }
-
-                            conn.receive_datagrams(
-                                voice_output_opt,
-                                self.gui_commands_transmitter.clone(),
-                            );
-                        }
-                        Err(err) => println!("Error during joining room: {}", err),
+                {
+                    self.report_error(ClientErrorKind::Chat, err.to_string())
+                        .await;
+                }
+            }
+            ClientMessage::ListAudioDevices {} => {
+                let gui_commands_transmitter = self.gui_commands_transmitter.clone();
+                tokio::spawn(async move {
+                    if let Ok(hosts) = tokio::task::spawn_blocking(list_audio_devices).await {
+                        let _ = gui_commands_transmitter
+                            .send(ClientMessage::AudioDevices { hosts })
+                            .await;
                     }
+                });
+            }
+            ClientMessage::SetAudioDevices {
+                input_device_id,
+                output_device_id,
+            } => {
+                let changed = input_device_id != self.input_device_id
+                    || output_device_id != self.output_device_id;
+                self.input_device_id = input_device_id;
+                self.output_device_id = output_device_id;
+                if changed && self.active_room.is_some() {
+                    self.stop_voice().await;
+                    self.start_voice().await;
+                } else if changed && self.mic_test_control_transmitter.is_some() {
+                    self.start_mic_test().await;
+                }
+            }
+            ClientMessage::SetVoiceSettings { voice_settings } => {
+                let requires_restart = voice_settings.requires_restart(&self.voice_settings);
+                let threshold_db = voice_settings.voice_activity_threshold_db;
+                self.voice_settings = voice_settings;
+                if requires_restart && self.active_room.is_some() {
+                    self.stop_voice_input().await;
+                    self.start_voice_input().await;
+                } else if requires_restart && self.mic_test_control_transmitter.is_some() {
+                    self.start_mic_test().await;
+                } else {
+                    self.send_voice_input_message(VoiceMessage::SetVoiceActivityThreshold {
+                        threshold_db,
+                    })
+                    .await;
+                    self.send_voice_input_message(VoiceMessage::SetEncoderSettings {
+                        encoder_settings: self.voice_settings.encoder,
+                    })
+                    .await;
+                    self.send_voice_input_message(VoiceMessage::SetInputGain {
+                        gain: self.voice_settings.input_gain,
+                        automatic: self.voice_settings.automatic_gain_control,
+                    })
+                    .await;
+                }
+                self.send_voice_output_message(VoiceMessage::SetOutputVolume {
+                    volume: self.voice_settings.output_volume,
+                })
+                .await;
+                self.send_voice_output_message(VoiceMessage::SetEncoderSettings {
+                    encoder_settings: self.voice_settings.encoder,
+                })
+                .await;
+            }
+            ClientMessage::SetVoiceActivityThreshold { threshold_db } => {
+                self.voice_settings.voice_activity_threshold_db = threshold_db;
+                self.send_voice_input_message(VoiceMessage::SetVoiceActivityThreshold {
+                    threshold_db,
+                })
+                .await;
+            }
+            ClientMessage::SetSelfVoiceState { muted, deafened } => {
+                self.self_muted = muted;
+                self.self_deafened = deafened;
+                if let Some(voice_input_control_transmitter) = &self.voice_input_control_transmitter
+                {
+                    let _ = voice_input_control_transmitter
+                        .send(VoiceMessage::SetMuted {
+                            muted: muted || deafened,
+                        })
+                        .await;
                 }
+                self.send_voice_output_message(VoiceMessage::SetDeafened { deafened })
+                    .await;
+                self.broadcast_voice_state().await;
+            }
+            ClientMessage::SetLevelMeter { enabled } => {
+                self.level_meter_enabled = enabled;
+                if self.active_room.is_some() {
+                    self.send_voice_input_message(VoiceMessage::SetLevelMeter { enabled })
+                        .await;
+                } else {
+                    self.update_mic_test().await;
+                }
+            }
+            ClientMessage::SetMicLoopback { enabled } => {
+                self.mic_loopback = enabled;
+                if self.mic_test_control_transmitter.is_some() {
+                    self.start_mic_test().await;
+                }
+            }
+            ClientMessage::PushToTalk { pressed } => {
+                self.send_voice_input_message(VoiceMessage::PushToTalk { pressed })
+                    .await;
+            }
+            ClientMessage::LeaveRoom {} => {
+                self.leave_room().await;
+            }
+            ClientMessage::Disconnect {} => {
+                self.disconnect().await;
             }
             ClientMessage::SetVoiceVolume { user_id, volume } => {
+                self.voice_volumes.insert(user_id, volume);
                 if let Some(voice_output_control_transmitter) =
                     &self.voice_output_control_transmitter
-                {
-                    match voice_output_control_transmitter
+                    && let Err(err) = voice_output_control_transmitter
                         .send(VoiceMessage::SetVoiceVolume { user_id, volume })
                         .await
-                    {
-                        Ok(_) => {}
-                        Err(err) => println!("Error during changing volume level: {}", err),
-                    }
+                {
+                    self.report_error(
+                        ClientErrorKind::VoiceOutput,
+                        format!("Couldn't change the volume: {}", err),
+                    )
+                    .await;
                 }
             }
+            ClientMessage::SetVoiceMuted { user_id, muted } => {
+                if muted {
+                    self.muted_voices.insert(user_id);
+                } else {
+                    self.muted_voices.remove(&user_id);
+                }
+                self.send_voice_output_message(VoiceMessage::SetVoiceMuted { user_id, muted })
+                    .await;
+            }
             _ => {}
         }
     }
+
+    async fn process_connection_events(&mut self, event: ConnectionEvent) {
+        match event {
+            ConnectionEvent::ConnectionLost {} => {
+                let Some(parameters) = self.session.clone() else {
+                    return;
+                };
+                self.stop_voice().await;
+                if let Some(mut conn) = self.server_connection.take() {
+                    conn.close();
+                }
+                self.server_connection_is_active = false;
+                self.reconnect_task = Some(ConnectionYawperClient::start_reconnecting(
+                    parameters,
+                    self.gui_commands_transmitter.clone(),
+                    self.connection_events_transmitter.clone(),
+                ));
+            }
+            ConnectionEvent::Reconnected { mut connection } => {
+                if self.reconnect_task.take().is_none() {
+                    connection.close();
+                    return;
+                }
+                self.activate_connection(connection).await;
+                if let Some((room_name, room_password)) = self.active_room.take() {
+                    self.join_room(room_name, room_password).await;
+                    if self.active_room.is_none() {
+                        let _ = self
+                            .gui_commands_transmitter
+                            .send(ClientMessage::RoomLeft {})
+                            .await;
+                    }
+                }
+            }
+            ConnectionEvent::RoomMemberJoined { user_id } => {
+                self.send_voice_output_message(VoiceMessage::AddUser { user_id })
+                    .await;
+                // Newcomers only learn about states that change, so tell them ours.
+                if self.self_muted || self.self_deafened {
+                    self.broadcast_voice_state().await;
+                }
+            }
+            ConnectionEvent::RoomMemberLeft { user_id } => {
+                self.send_voice_output_message(VoiceMessage::RemoveUser { user_id })
+                    .await;
+            }
+            ConnectionEvent::ReconnectFailed { reason } => {
+                if self.reconnect_task.take().is_none() {
+                    return;
+                }
+                self.report_error(ClientErrorKind::Connection, reason).await;
+                self.disconnect().await;
+            }
+        }
+    }
+
+    async fn activate_connection(&mut self, mut new_server_connection: ConnectionYawperClient) {
+        new_server_connection.start_updates(
+            self.gui_commands_transmitter.clone(),
+            self.connection_events_transmitter.clone(),
+        );
+        new_server_connection.watch_connection(self.connection_events_transmitter.clone());
+        let user_id = new_server_connection.user_id;
+        if let Some(err) = new_server_connection.introduction_error.take() {
+            self.report_error(
+                ClientErrorKind::Connection,
+                format!("Server didn't accept the display name: {}", err),
+            )
+            .await;
+        }
+        self.server_connection = Some(new_server_connection);
+        self.server_connection_is_active = true;
+        let _ = self
+            .gui_commands_transmitter
+            .send(ClientMessage::ConnectionIsActive { user_id })
+            .await;
+    }
+
+    async fn join_room(&mut self, room_name: String, room_password: String) {
+        if !self.server_connection_is_active {
+            return;
+        }
+        if self.active_room.is_some() {
+            self.leave_room().await;
+        }
+        let Some(conn) = &self.server_connection else {
+            return;
+        };
+        match conn
+            .send_command(ClientMessage::JoinRoom {
+                room_name: room_name.clone(),
+                room_password: room_password.clone(),
+            })
+            .await
+        {
+            Ok(_) => {
+                self.active_room = Some((room_name.clone(), room_password));
+                self.stop_mic_test().await;
+                let _ = self
+                    .gui_commands_transmitter
+                    .send(ClientMessage::RoomJoined { room_name })
+                    .await;
+                self.start_voice().await;
+                if self.self_muted || self.self_deafened {
+                    self.broadcast_voice_state().await;
+                }
+            }
+            Err(err) => {
+                self.report_error(ClientErrorKind::RoomJoin, err.to_string())
+                    .await;
+            }
+        }
+    }
+
+    async fn leave_room(&mut self) {
+        if self.active_room.take().is_none() {
+            return;
+        }
+        self.stop_voice().await;
+        if let Some(conn) = &self.server_connection
+            && let Err(err) = conn.send_command(ClientMessage::LeaveRoom {}).await
+        {
+            self.report_error(ClientErrorKind::RoomLeave, err.to_string())
+                .await;
+        }
+        let _ = self
+            .gui_commands_transmitter
+            .send(ClientMessage::RoomLeft {})
+            .await;
+        self.update_mic_test().await;
+    }
+
+    /// Opens the audio devices for the active room. A missing device is reported and skipped,
+    /// so the user can still listen without a microphone or talk without speakers.
+    async fn start_voice(&mut self) {
+        self.start_voice_input().await;
+
+        let (voice_output_control_transmitter, voice_output_control_receiver) =
+            mpsc::channel::<VoiceMessage>(100);
+        let voice_output_opt = match VoiceOutput::new(
+            voice_output_control_receiver,
+            self.output_device_id.as_deref(),
+            self.voice_settings.output_volume,
+            self.voice_settings.encoder.forward_error_correction,
+        ) {
+            Ok(voice_output) => {
+                let _ = voice_output_control_transmitter
+                    .send(VoiceMessage::SetDeafened {
+                        deafened: self.self_deafened,
+                    })
+                    .await;
+                for (&user_id, &volume) in &self.voice_volumes {
+                    let _ = voice_output_control_transmitter
+                        .send(VoiceMessage::SetVoiceVolume { user_id, volume })
+                        .await;
+                }
+                for &user_id in &self.muted_voices {
+                    let _ = voice_output_control_transmitter
+                        .send(VoiceMessage::SetVoiceMuted {
+                            user_id,
+                            muted: true,
+                        })
+                        .await;
+                }
+                self.voice_output_control_transmitter = Some(voice_output_control_transmitter);
+                Some(voice_output)
+            }
+            Err(err) => {
+                self.report_error(ClientErrorKind::VoiceOutput, err.to_string())
+                    .await;
+                None
+            }
+        };
+
+        if let Some(conn) = &mut self.server_connection {
+            conn.receive_datagrams(voice_output_opt, self.gui_commands_transmitter.clone());
+        }
+    }
+
+    async fn start_voice_input(&mut self) {
+        let Some(conn) = &self.server_connection else {
+            return;
+        };
+        let sink = VoiceSink::Server(conn.connection.clone());
+        let muted = self.self_muted || self.self_deafened;
+        self.voice_input_control_transmitter = self.spawn_voice_input(sink, muted).await;
+    }
+
+    async fn spawn_voice_input(
+        &self,
+        sink: VoiceSink,
+        muted: bool,
+    ) -> Option<Sender<VoiceMessage>> {
+        let (voice_input_control_transmitter, voice_input_control_receiver) =
+            mpsc::channel::<VoiceMessage>(100);
+        let voice_input = match VoiceInput::new(
+            voice_input_control_receiver,
+            sink,
+            self.input_device_id.as_deref(),
+            &self.voice_settings,
+            self.gui_commands_transmitter.clone(),
+        ) {
+            Ok(voice_input) => voice_input,
+            Err(err) => {
+                self.report_error(ClientErrorKind::VoiceInput, err.to_string())
+                    .await;
+                return None;
+            }
+        };
+        if let Err(err) = voice_input.run() {
+            self.report_error(ClientErrorKind::VoiceInput, err.to_string())
+                .await;
+            return None;
+        }
+        let _ = voice_input_control_transmitter
+            .send(VoiceMessage::SetMuted { muted })
+            .await;
+        let _ = voice_input_control_transmitter
+            .send(VoiceMessage::SetLevelMeter {
+                enabled: self.level_meter_enabled,
+            })
+            .await;
+        Some(voice_input_control_transmitter)
+    }
+
+    /// Runs the microphone outside of a room, for the level meter and the loopback test.
+    /// Nothing is sent to the server, with loopback enabled the encoded voice is played back.
+    async fn start_mic_test(&mut self) {
+        self.stop_mic_test().await;
+        let sink = if self.mic_loopback {
+            let (voice_output_control_transmitter, voice_output_control_receiver) =
+                mpsc::channel::<VoiceMessage>(100);
+            match VoiceOutput::new(
+                voice_output_control_receiver,
+                self.output_device_id.as_deref(),
+                self.voice_settings.output_volume,
+                self.voice_settings.encoder.forward_error_correction,
+            ) {
+                Ok(voice_output) => {
+                    let (packet_transmitter, packet_receiver) = mpsc::channel(50);
+                    voice_output.play_loopback(packet_receiver);
+                    self.mic_test_output_control_transmitter =
+                        Some(voice_output_control_transmitter);
+                    VoiceSink::Loopback(packet_transmitter)
+                }
+                Err(err) => {
+                    self.report_error(ClientErrorKind::VoiceOutput, err.to_string())
+                        .await;
+                    VoiceSink::Discard
+                }
+            }
+        } else {
+            VoiceSink::Discard
+        };
+        self.mic_test_control_transmitter = self.spawn_voice_input(sink, false).await;
+    }
+
+    async fn stop_mic_test(&mut self) {
+        self.mic_test_output_control_transmitter = None;
+        if let Some(mic_test_control_transmitter) = self.mic_test_control_transmitter.take() {
+            let _ = mic_test_control_transmitter
+                .send(VoiceMessage::CloseVoiceInput {})
+                .await;
+        }
+    }
+
+    /// Starts or stops the mic test to match the settings page, it never runs inside a room.
+    async fn update_mic_test(&mut self) {
+        if self.level_meter_enabled && self.active_room.is_none() {
+            self.start_mic_test().await;
+        } else {
+            self.stop_mic_test().await;
+        }
+    }
+
+    async fn stop_voice(&mut self) {
+        self.stop_voice_input().await;
+        // The voice output lives inside the datagram task, stopping that task releases the device.
+        self.voice_output_control_transmitter = None;
+        if let Some(conn) = &mut self.server_connection {
+            conn.stop_receiving_datagrams();
+        }
+    }
+
+    /// Sends to the room's voice input, or to the mic test when not in a room.
+    async fn send_voice_input_message(&self, message: VoiceMessage) {
+        if let Some(voice_input_control_transmitter) = self
+            .voice_input_control_transmitter
+            .as_ref()
+            .or(self.mic_test_control_transmitter.as_ref())
+        {
+            let _ = voice_input_control_transmitter.send(message).await;
+        }
+    }
+
+    /// Sends to the room's voice output, or to the mic test loopback when not in a room.
+    async fn send_voice_output_message(&self, message: VoiceMessage) {
+        if let Some(voice_output_control_transmitter) = self
+            .voice_output_control_transmitter
+            .as_ref()
+            .or(self.mic_test_output_control_transmitter.as_ref())
+        {
+            let _ = voice_output_control_transmitter.send(message).await;
+        }
+    }
+
+    /// Tells the other room members whether we are muted or deafened.
+    async fn broadcast_voice_state(&self) {
+        if self.active_room.is_none() {
+            return;
+        }
+        if let Some(conn) = &self.server_connection
+            && let Err(err) = conn
+                .send_command(ClientMessage::SetSelfVoiceState {
+                    muted: self.self_muted,
+                    deafened: self.self_deafened,
+                })
+                .await
+        {
+            self.report_error(
+                ClientErrorKind::Connection,
+                format!("Couldn't share your mute state: {}", err),
+            )
+            .await;
+        }
+    }
+
+    async fn stop_voice_input(&mut self) {
+        if let Some(voice_input_control_transmitter) = self.voice_input_control_transmitter.take() {
+            let _ = voice_input_control_transmitter
+                .send(VoiceMessage::CloseVoiceInput {})
+                .await;
+        }
+    }
+
+    async fn disconnect(&mut self) {
+        if let Some(reconnect_task) = self.reconnect_task.take() {
+            reconnect_task.abort();
+        }
+        self.session = None;
+        // User ids are only meaningful on the server that handed them out.
+        self.voice_volumes.clear();
+        self.muted_voices.clear();
+        let was_in_room = self.active_room.take().is_some();
+        self.stop_voice().await;
+        if was_in_room {
+            self.update_mic_test().await;
+        }
+        if let Some(mut conn) = self.server_connection.take() {
+            conn.close();
+        }
+        self.server_connection_is_active = false;
+        let _ = self
+            .gui_commands_transmitter
+            .send(ClientMessage::Disconnected {})
+            .await;
+    }
+
+    async fn report_error(&self, kind: ClientErrorKind, message: String) {
+        let _ = self
+            .gui_commands_transmitter
+            .send(ClientMessage::Error { kind, message })
+            .await;
+    }
 }
@@ -0,0 +1,270 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        oneshot,
+    },
+    task::JoinHandle,
+    time::timeout,
+};
+use wtransport::{Connection, SendStream};
+
+use crate::messages::control_frame::{ControlFrame, ControlPayload, PUSH_REQUEST_ID};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_FRAME_LEN: usize = 1024 * 1024;
+
+type PendingRequests = Arc<Mutex<HashMap<u64, oneshot::Sender<ControlPayload>>>>;
+
+/// The long-lived bidirectional stream carrying every lobby and room command of a connection.
+pub struct ControlStream {
+    frame_transmitter: Sender<ControlFrame>,
+    pending_requests: PendingRequests,
+    next_request_id: AtomicU64,
+    writer_task: JoinHandle<()>,
+    reader_task: JoinHandle<()>,
+}
+
+impl ControlStream {
+    /// Opens the control stream. Frames the server sends without a matching request are
+    /// delivered through the returned receiver.
+    pub async fn open(
+        connection: &Connection,
+    ) -> Result<(Self, Receiver<ControlPayload>), Box<dyn Error>> {
+        let (send, recv) = connection.open_bi().await?.await?;
+        let (frame_transmitter, frame_receiver) = mpsc::channel::<ControlFrame>(100);
+        let (push_transmitter, push_receiver) = mpsc::channel::<ControlPayload>(100);
+        let pending_requests = PendingRequests::default();
+
+        let writer_task = tokio::spawn(write_frames(send, frame_receiver));
+        let reader_task = tokio::spawn(read_frames(
+            recv,
+            pending_requests.clone(),
+            push_transmitter,
+        ));
+
+        Ok((
+            Self {
+                frame_transmitter,
+                pending_requests,
+                next_request_id: AtomicU64::new(PUSH_REQUEST_ID + 1),
+                writer_task,
+                reader_task,
+            },
+            push_receiver,
+        ))
+    }
+
+    /// Sends a payload and waits for the response carrying the same request id.
+    pub async fn request(&self, payload: ControlPayload) -> Result<ControlPayload, Box<dyn Error>> {
+        self.request_within(payload, REQUEST_TIMEOUT).await
+    }
+
+    /// Like [`Self::request`], but gives up once `limit` has passed without a response.
+    pub async fn request_within(
+        &self,
+        payload: ControlPayload,
+        limit: Duration,
+    ) -> Result<ControlPayload, Box<dyn Error>> {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (response_transmitter, response_receiver) = oneshot::channel();
+        self.pending_requests
+            .lock()
+            .unwrap()
+            .insert(request_id, response_transmitter);
+
+        let sent = self
+            .frame_transmitter
+            .send(ControlFrame {
+                request_id,
+                payload,
+            })
+            .await;
+        let response = match sent {
+            Ok(_) => timeout(limit, response_receiver).await,
+            Err(_) => {
+                self.pending_requests.lock().unwrap().remove(&request_id);
+                return Err("Control stream closed".into());
+            }
+        };
+
+        match response {
+            Ok(Ok(payload)) => Ok(payload),
+            Ok(Err(_)) => Err("Control stream closed".into()),
+            Err(_) => {
+                self.pending_requests.lock().unwrap().remove(&request_id);
+                Err("Server didn't respond in time".into())
+            }
+        }
+    }
+
+    /// Sends a payload that doesn't expect a response.
+    pub async fn send(&self, payload: ControlPayload) -> Result<(), Box<dyn Error>> {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        self.frame_transmitter
+            .send(ControlFrame {
+                request_id,
+                payload,
+            })
+            .await
+            .map_err(|_| "Control stream closed".into())
+    }
+}
+
+impl Drop for ControlStream {
+    fn drop(&mut self) {
+        self.writer_task.abort();
+        self.reader_task.abort();
+    }
+}
+
+async fn write_frames(mut send: SendStream, mut frame_receiver: Receiver<ControlFrame>) {
+    while let Some(frame) = frame_receiver.recv().await {
+        let bytes = match bincode::serialize(&frame) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                println!("Error during serializing control frame: {}", err);
+                continue;
+            }
+        };
+        let len = (bytes.len() as u32).to_be_bytes();
+        if let Err(err) = send.write_all(&len).await {
+            println!("Error during writing control frame: {}", err);
+            return;
+        }
+        if let Err(err) = send.write_all(&bytes).await {
+            println!("Error during writing control frame: {}", err);
+            return;
+        }
+    }
+    let _ = send.finish().await;
+}
+
+async fn read_frames(
+    mut recv: impl AsyncRead + Unpin,
+    pending_requests: PendingRequests,
+    push_transmitter: Sender<ControlPayload>,
+) {
+    let mut len = [0u8; 4];
+    let mut buffer = Vec::new();
+    loop {
+        if let Err(err) = recv.read_exact(&mut len).await {
+            println!("Control stream closed: {}", err);
+            break;
+        }
+        let frame_len = u32::from_be_bytes(len) as usize;
+        if frame_len > MAX_FRAME_LEN {
+            println!("Control frame of {} bytes is too large", frame_len);
+            break;
+        }
+        buffer.resize(frame_len, 0);
+        if let Err(err) = recv.read_exact(&mut buffer).await {
+            println!("Control stream closed: {}", err);
+            break;
+        }
+
+        let frame: ControlFrame = match bincode::deserialize(&buffer) {
+            Ok(frame) => frame,
+            Err(err) => {
+                println!("Couldn't deserialize control frame: {}", err);
+                continue;
+            }
+        };
+
+        let response_transmitter = pending_requests.lock().unwrap().remove(&frame.request_id);
+        match response_transmitter {
+            Some(response_transmitter) => {
+                let _ = response_transmitter.send(frame.payload);
+            }
+            None => {
+                if push_transmitter.send(frame.payload).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    // Dropping the pending senders fails every request still waiting for a response.
+    pending_requests.lock().unwrap().clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::lobby_message::LobbyMessage;
+
+    fn encode(request_id: u64) -> Vec<u8> {
+        let frame = ControlFrame {
+            request_id,
+            payload: ControlPayload::Lobby(LobbyMessage::Subscribe {}),
+        };
+        let bytes = bincode::serialize(&frame).unwrap();
+        let mut encoded = (bytes.len() as u32).to_be_bytes().to_vec();
+        encoded.extend(bytes);
+        encoded
+    }
+
+    async fn read_all(
+        stream: &[u8],
+        pending_request_ids: &[u64],
+    ) -> (
+        Vec<oneshot::Receiver<ControlPayload>>,
+        Receiver<ControlPayload>,
+    ) {
+        let pending_requests = PendingRequests::default();
+        let mut response_receivers = Vec::new();
+        for request_id in pending_request_ids {
+            let (response_transmitter, response_receiver) = oneshot::channel();
+            pending_requests
+                .lock()
+                .unwrap()
+                .insert(*request_id, response_transmitter);
+            response_receivers.push(response_receiver);
+        }
+        let (push_transmitter, push_receiver) = mpsc::channel(10);
+        read_frames(stream, pending_requests, push_transmitter).await;
+        (response_receivers, push_receiver)
+    }
+
+    #[tokio::test]
+    async fn responses_reach_their_request_and_unknown_ids_are_pushed() {
+        let mut stream = encode(7);
+        stream.extend(encode(42));
+        stream.extend(encode(PUSH_REQUEST_ID));
+        let (mut response_receivers, mut push_receiver) = read_all(&stream, &[7]).await;
+
+        assert!(response_receivers.remove(0).await.is_ok());
+        assert!(push_receiver.recv().await.is_some());
+        assert!(push_receiver.recv().await.is_some());
+        assert!(push_receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn oversize_frame_closes_the_stream() {
+        let mut stream = ((MAX_FRAME_LEN + 1) as u32).to_be_bytes().to_vec();
+        stream.extend(encode(7));
+        let (mut response_receivers, mut push_receiver) = read_all(&stream, &[7]).await;
+
+        assert!(response_receivers.remove(0).await.is_err());
+        assert!(push_receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn truncated_frame_fails_pending_requests() {
+        let mut stream = encode(7);
+        stream.truncate(stream.len() - 1);
+        let (mut response_receivers, mut push_receiver) = read_all(&stream, &[7]).await;
+
+        assert!(response_receivers.remove(0).await.is_err());
+        assert!(push_receiver.recv().await.is_none());
+    }
+}
@@ -1,3 +1,4 @@
 pub mod backend;
+mod control_stream;
 pub mod server_connection;
 mod voice_channel;
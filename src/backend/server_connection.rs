@@ -1,119 +1,274 @@
-use std::{error::Error, sync::Arc, time::Duration};
+use std::{error::Error, future::pending, sync::Arc, time::Duration};
 
-use tokio::{io::AsyncReadExt, sync::mpsc::Sender, time::sleep};
-use wtransport::{ClientConfig, Connection, Endpoint};
+use tokio::{
+    select,
+    sync::mpsc::{Receiver, Sender},
+    task::JoinHandle,
+    time::{Interval, MissedTickBehavior, interval, sleep},
+};
+use wtransport::{ClientConfig, Connection, Endpoint, VarInt};
+
+use crate::{
+    messages::{
+        client_error::ClientErrorKind, client_message::ClientMessage,
+        connection_event::ConnectionEvent, control_frame::ControlPayload,
+        lobby_message::LobbyMessage, room_message::RoomMessage, voice_message::VoiceMessage,
+    },
+    settings::certificate_mode::{CertificateMismatch, CertificateMode},
+};
 
-use crate::messages::{
-    client_message::ClientMessage, lobby_message::LobbyMessage, room_message::RoomMessage,
+use super::{
+    control_stream::ControlStream,
+    voice_channel::voice_output::{VoiceOutput, activity_interval, playout_interval},
 };
 
-use super::voice_channel::voice_output::VoiceOutput;
+const DISCONNECT_CODE: u32 = 0;
+const CERTIFICATE_REJECTED_CODE: u32 = 1;
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+const RECONNECT_MAX_ATTEMPTS: u32 = 10;
+const ROOM_LIST_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Kept short since servers without profiles may never answer and every connect waits for it.
+const INTRODUCE_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Clone)]
+pub struct ConnectionParameters {
+    pub host_name: String,
+    pub host_password: String,
+    pub certificate_mode: CertificateMode,
+    pub client_id: u64,
+    pub display_name: String,
+    pub color: [u8; 3],
+}
 
 pub struct ConnectionYawperClient {
     pub connection: Arc<Connection>,
+    pub certificate_fingerprint: Option<String>,
+    pub user_id: Option<u64>,
+    /// Why the server didn't take the display name and color, the connection works without them.
+    pub introduction_error: Option<String>,
+    control: Arc<ControlStream>,
+    push_receiver: Option<Receiver<ControlPayload>>,
+    updates_task: Option<JoinHandle<()>>,
+    datagrams_task: Option<JoinHandle<()>>,
+    watch_task: Option<JoinHandle<()>>,
 }
 
 impl ConnectionYawperClient {
-    pub async fn new(host_name: String, host_password: String) -> Result<Self, Box<dyn Error>> {
-        let config = ClientConfig::builder()
-            .with_bind_default()
-            .with_no_cert_validation()
+    pub async fn new(parameters: &ConnectionParameters) -> Result<Self, Box<dyn Error>> {
+        let certificate_mode = &parameters.certificate_mode;
+        let builder = ClientConfig::builder().with_bind_default();
+        // Pinned and trust-on-first-use modes check the fingerprint once the handshake is done,
+        // the handshake itself still verifies that the server owns the presented certificate.
+        let builder = if certificate_mode.validates_chain() {
+            builder.with_native_certs()
+        } else {
+            builder.with_no_cert_validation()
+        };
+        let config = builder
             .keep_alive_interval(Some(Duration::from_secs(20)))
             .build();
 
         let connection = Endpoint::client(config)?
-            .connect(host_name.as_str().trim())
+            .connect(parameters.host_name.as_str().trim())
             .await?;
 
+        let peer_certificate_hash = connection.peer_identity().and_then(|chain| {
+            chain
+                .as_slice()
+                .first()
+                .map(|certificate| certificate.hash())
+        });
+        if let Err(err) = certificate_mode.verify(peer_certificate_hash.as_ref()) {
+            connection.close(
+                VarInt::from_u32(CERTIFICATE_REJECTED_CODE),
+                b"certificate rejected",
+            );
+            return Err(err);
+        }
+
         let (mut send, _) = connection.open_bi().await?.await?;
-        send.write_all(host_password.as_str().trim().as_bytes())
+        send.write_all(parameters.host_password.as_str().trim().as_bytes())
             .await?;
 
         send.finish().await?;
 
-        let msg = RoomMessage::TxtMessage {
-            body: "Test message".to_string(),
-            user_id: u64::MAX,
+        let (control, push_receiver) = ControlStream::open(&connection).await?;
+        let introduction = LobbyMessage::Introduce {
+            client_id: parameters.client_id,
+            display_name: parameters.display_name.trim().to_string(),
+            color: parameters.color,
+        };
+        // Servers without profiles answer something else, connect without a user id.
+        let introduced = control
+            .request_within(ControlPayload::Lobby(introduction), INTRODUCE_TIMEOUT)
+            .await
+            .map_err(|err| err.to_string());
+        let (user_id, introduction_error) = match introduced {
+            Ok(ControlPayload::Lobby(LobbyMessage::Introduced { user_id })) => {
+                (Some(user_id), None)
+            }
+            Ok(_) => (None, None),
+            Err(err) => (None, Some(err)),
         };
-        let mut send = connection.open_uni().await?.await?;
-        let bytes = bincode::serialize(&msg).unwrap();
-        send.write_all(&bytes).await?;
-        send.finish().await?;
-
         let connection = Arc::new(connection);
 
-        Ok(Self { connection })
+        Ok(Self {
+            connection,
+            certificate_fingerprint: peer_certificate_hash.map(|hash| hash.to_string()),
+            user_id,
+            introduction_error,
+            control: Arc::new(control),
+            push_receiver: Some(push_receiver),
+            updates_task: None,
+            datagrams_task: None,
+            watch_task: None,
+        })
     }
 
-    pub fn start_updates(self: &Self, gui_commands_transmitter: Sender<ClientMessage>) {
+    pub fn watch_connection(&mut self, connection_events_transmitter: Sender<ConnectionEvent>) {
         let connection = self.connection.clone();
+        self.watch_task = Some(tokio::spawn(async move {
+            connection.closed().await;
+            let _ = connection_events_transmitter
+                .send(ConnectionEvent::ConnectionLost {})
+                .await;
+        }));
+    }
+
+    pub fn start_reconnecting(
+        parameters: ConnectionParameters,
+        gui_commands_transmitter: Sender<ClientMessage>,
+        connection_events_transmitter: Sender<ConnectionEvent>,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
-            loop {
-                if gui_commands_transmitter.is_closed() {
-                    return;
-                }
+            let mut retry_in = RECONNECT_INITIAL_DELAY;
+            let mut last_error = String::new();
+            for attempt in 1..=RECONNECT_MAX_ATTEMPTS {
+                let _ = gui_commands_transmitter
+                    .send(ClientMessage::Reconnecting { attempt, retry_in })
+                    .await;
+                sleep(retry_in).await;
 
-                let (mut send, mut recv) = match connection.open_bi().await {
-                    Ok(opening) => match opening.await {
-                        Ok(val) => val,
-                        Err(err) => {
-                            println!("{}", err);
-                            return;
-                        }
-                    },
-                    Err(err) => {
-                        println!("{}", err);
+                let connection = ConnectionYawperClient::new(&parameters)
+                    .await
+                    .map_err(|err| (err.is::<CertificateMismatch>(), err.to_string()));
+                match connection {
+                    Ok(connection) => {
+                        let _ = connection_events_transmitter
+                            .send(ConnectionEvent::Reconnected { connection })
+                            .await;
                         return;
                     }
-                };
-
-                let msg = LobbyMessage::ListRooms {};
-                match send.write_all(&bincode::serialize(&msg).unwrap()).await {
-                    Ok(_) => {}
-                    Err(err) => {
-                        println!("{}", err);
-                        return;
+                    // A changed certificate won't fix itself, retrying only repeats the warning.
+                    Err((true, err)) => {
+                        last_error = err;
+                        break;
                     }
+                    Err((false, err)) => last_error = err,
                 }
-                match send.finish().await {
-                    Ok(_) => {}
-                    Err(err) => {
-                        println!("{}", err);
-                        return;
+                retry_in = (retry_in * 2).min(RECONNECT_MAX_DELAY);
+            }
+            let _ = connection_events_transmitter
+                .send(ConnectionEvent::ReconnectFailed { reason: last_error })
+                .await;
+        })
+    }
+
+    pub fn start_updates(
+        &mut self,
+        gui_commands_transmitter: Sender<ClientMessage>,
+        connection_events_transmitter: Sender<ConnectionEvent>,
+    ) {
+        let Some(mut push_receiver) = self.push_receiver.take() else {
+            return;
+        };
+        let control = self.control.clone();
+        let connection = self.connection.clone();
+        self.updates_task = Some(tokio::spawn(async move {
+            let subscribed = control
+                .request(ControlPayload::Lobby(LobbyMessage::Subscribe {}))
+                .await
+                .map_err(|err| err.to_string());
+            // Servers without room event support are polled for the whole room list instead.
+            let mut room_list_poll = match subscribed {
+                Ok(ControlPayload::Lobby(LobbyMessage::Subscribed { rooms })) => {
+                    let room_names = rooms.iter().map(|(room_name, _)| room_name.clone());
+                    let _ = gui_commands_transmitter
+                        .send(ClientMessage::RoomList {
+                            rooms: room_names.collect(),
+                        })
+                        .await;
+                    for (room_name, user_count) in rooms {
+                        let _ = gui_commands_transmitter
+                            .send(ClientMessage::RoomUserCount {
+                                room_name,
+                                user_count,
+                            })
+                            .await;
                     }
+                    None
+                }
+                Ok(_) | Err(_) => {
+                    let mut room_list_poll = interval(ROOM_LIST_POLL_INTERVAL);
+                    room_list_poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                    Some(room_list_poll)
                 }
+            };
 
-                let mut buffer = Vec::new();
-                match recv.read_to_end(&mut buffer).await {
-                    Ok(_) => {}
-                    Err(err) => {
-                        println!("{}", err);
-                        return;
-                    }
+            loop {
+                if gui_commands_transmitter.is_closed() {
+                    return;
                 }
 
-                match bincode::deserialize(&buffer) {
-                    Ok(LobbyMessage::ListRoomsResult { rooms }) => {
-                        let _ = gui_commands_transmitter
-                            .send(ClientMessage::RoomList { rooms })
-                            .await;
-                    }
-                    Err(err) => {
-                        println!("{}", err);
-                        return;
+                select! {
+                    _ = next_room_list_poll(&mut room_list_poll) => {
+                        let response = control
+                            .request(ControlPayload::Lobby(LobbyMessage::ListRooms {}))
+                            .await
+                            .map_err(|err| err.to_string());
+                        match response {
+                            Ok(payload) => {
+                                process_control_payload(
+                                    payload,
+                                    &gui_commands_transmitter,
+                                    &connection_events_transmitter,
+                                )
+                                .await;
+                            }
+                            Err(err) => {
+                                let _ = gui_commands_transmitter
+                                    .send(ClientMessage::Error {
+                                        kind: ClientErrorKind::Connection,
+                                        message: format!("Room list update failed: {}", err),
+                                    })
+                                    .await;
+                                // Closing hands over to the watch task, which starts reconnecting.
+                                connection.close(
+                                    VarInt::from_u32(DISCONNECT_CODE),
+                                    b"control stream failed",
+                                );
+                                return;
+                            }
+                        }
                     }
-                    _ => {
-                        println!("Couldn't deserialize room list!");
-                        return;
+                    push = push_receiver.recv() => match push {
+                        Some(payload) => {
+                            process_control_payload(
+                                payload,
+                                &gui_commands_transmitter,
+                                &connection_events_transmitter,
+                            )
+                            .await;
+                        }
+                        None => return,
                     }
                 }
-
-                sleep(Duration::from_millis(100)).await;
             }
-        });
+        }));
     }
 
-    pub async fn send_command(self: &Self, message: ClientMessage) -> Result<(), Box<dyn Error>> {
+    pub async fn send_command(&self, message: ClientMessage) -> Result<(), Box<dyn Error>> {
         match message {
             ClientMessage::CreateRoom {
                 room_name,
@@ -123,11 +278,7 @@ impl ConnectionYawperClient {
                     room_name: room_name.trim().to_string(),
                     password: room_password.trim().to_string(),
                 };
-                let (mut send, _) = self.connection.open_bi().await?.await?;
-                let bytes = bincode::serialize(&msg)?;
-                send.write_all(&bytes).await?;
-                send.finish().await?;
-                Ok(())
+                self.control.send(ControlPayload::Lobby(msg)).await
             }
             ClientMessage::JoinRoom {
                 room_name,
@@ -137,64 +288,217 @@ impl ConnectionYawperClient {
                     room_name: room_name.trim().to_string(),
                     password: room_password.trim().to_string(),
                 };
-                let (mut send, mut recv) = self.connection.open_bi().await?.await?;
-                let bytes = bincode::serialize(&msg).unwrap();
-                send.write_all(&bytes).await?;
-                send.finish().await?;
-
-                let mut buffer = Vec::new();
-                recv.read_to_end(&mut buffer).await?;
-
-                let room_message: RoomMessage = bincode::deserialize(&buffer).unwrap();
-
-                if let RoomMessage::Connected {} = room_message {
-                    Ok(())
-                } else {
-                    Err("Server didn't connect to the room".into())
+                match self.control.request(ControlPayload::Lobby(msg)).await? {
+                    ControlPayload::Room(RoomMessage::Connected {}) => Ok(()),
+                    _ => Err("Server didn't connect to the room".into()),
                 }
             }
+            ClientMessage::SendChatMessage { body } => {
+                // The server fills in the author, the same as for voice packets.
+                let msg = RoomMessage::TxtMessage {
+                    body,
+                    user_id: u64::MAX,
+                };
+                self.control.send(ControlPayload::Room(msg)).await
+            }
+            ClientMessage::SetSelfVoiceState { muted, deafened } => {
+                let msg = RoomMessage::VoiceState {
+                    user_id: u64::MAX,
+                    muted,
+                    deafened,
+                };
+                self.control.send(ControlPayload::Room(msg)).await
+            }
+            ClientMessage::LeaveRoom {} => {
+                let msg = LobbyMessage::ExitRoom {};
+                self.control.send(ControlPayload::Lobby(msg)).await
+            }
             _ => Ok(()),
         }
     }
 
     pub fn receive_datagrams(
-        self: &Self,
+        &mut self,
         mut voice_output_opt: Option<VoiceOutput>,
         gui_commands_transmitter_clone: Sender<ClientMessage>,
     ) {
+        self.stop_receiving_datagrams();
         let connection_clone = self.connection.clone();
-        tokio::spawn(async move {
+        self.datagrams_task = Some(tokio::spawn(async move {
+            let mut playout_interval = playout_interval();
+            let mut activity_interval = activity_interval();
             loop {
-                match connection_clone.receive_datagram().await {
-                    Ok(data) => {
-                        let message: RoomMessage = bincode::deserialize(&data).unwrap();
-                        match message {
-                            RoomMessage::VoicePacket {
+                select! {
+                    datagram = connection_clone.receive_datagram() => match datagram {
+                        Ok(data) => {
+                            if let Ok(RoomMessage::VoicePacket {
                                 body,
                                 order_id,
                                 user_id,
-                            } => {
-                                if let Some(voice_output) = &mut voice_output_opt {
-                                    let added_voice_channel =
-                                        voice_output.accept_packet(body, order_id, user_id);
-                                    if added_voice_channel != u64::MAX {
-                                        let _ = gui_commands_transmitter_clone
-                                            .send(ClientMessage::NewVoiceChannel {
-                                                user_id: added_voice_channel,
-                                            })
-                                            .await;
-                                    }
+                            }) = bincode::deserialize(&data)
+                                && let Some(voice_output) = &mut voice_output_opt
+                            {
+                                let added_voice_channel =
+                                    voice_output.accept_packet(body, order_id, user_id);
+                                if added_voice_channel != u64::MAX {
+                                    let _ = gui_commands_transmitter_clone
+                                        .send(ClientMessage::NewVoiceChannel {
+                                            user_id: added_voice_channel,
+                                        })
+                                        .await;
                                 }
                             }
-                            _ => {}
+                        }
+                        Err(err) => {
+                            println!("Error during receiving datagram: {}", err);
+                            break;
+                        }
+                    },
+                    _ = playout_interval.tick(), if voice_output_opt.is_some() => {
+                        if let Some(voice_output) = &mut voice_output_opt {
+                            voice_output.play_out();
                         }
                     }
-                    Err(err) => {
-                        println!("Error during receiving datagram: {}", err);
-                        break;
+                    _ = activity_interval.tick(), if voice_output_opt.is_some() => {
+                        if let Some(voice_output) = &mut voice_output_opt
+                            && let Some(levels) = voice_output.voice_activity()
+                        {
+                            let _ = gui_commands_transmitter_clone
+                                .send(ClientMessage::VoiceActivity { levels })
+                                .await;
+                        }
+                    }
+                    message = next_voice_message(&mut voice_output_opt) => {
+                        if let Some(voice_output) = &mut voice_output_opt {
+                            voice_output.process_control_message(message);
+                        }
                     }
                 }
             }
-        });
+        }));
+    }
+
+    pub fn stop_receiving_datagrams(&mut self) {
+        if let Some(datagrams_task) = self.datagrams_task.take() {
+            datagrams_task.abort();
+        }
+    }
+
+    pub fn close(&mut self) {
+        if let Some(watch_task) = self.watch_task.take() {
+            watch_task.abort();
+        }
+        self.stop_receiving_datagrams();
+        if let Some(updates_task) = self.updates_task.take() {
+            updates_task.abort();
+        }
+        self.connection
+            .close(VarInt::from_u32(DISCONNECT_CODE), b"client disconnected");
+    }
+}
+
+async fn process_control_payload(
+    payload: ControlPayload,
+    gui_commands_transmitter: &Sender<ClientMessage>,
+    connection_events_transmitter: &Sender<ConnectionEvent>,
+) {
+    match payload {
+        ControlPayload::Lobby(LobbyMessage::ListRoomsResult { rooms }) => {
+            let _ = gui_commands_transmitter
+                .send(ClientMessage::RoomList { rooms })
+                .await;
+        }
+        ControlPayload::Lobby(LobbyMessage::RoomCreated { room_name }) => {
+            let _ = gui_commands_transmitter
+                .send(ClientMessage::RoomCreated { room_name })
+                .await;
+        }
+        ControlPayload::Lobby(LobbyMessage::RoomDeleted { room_name }) => {
+            let _ = gui_commands_transmitter
+                .send(ClientMessage::RoomDeleted { room_name })
+                .await;
+        }
+        ControlPayload::Lobby(LobbyMessage::UserJoinedRoom { room_name, .. }) => {
+            let _ = gui_commands_transmitter
+                .send(ClientMessage::RoomUserJoined { room_name })
+                .await;
+        }
+        ControlPayload::Lobby(LobbyMessage::UserLeftRoom { room_name, .. }) => {
+            let _ = gui_commands_transmitter
+                .send(ClientMessage::RoomUserLeft { room_name })
+                .await;
+        }
+        ControlPayload::Lobby(LobbyMessage::RoomUserCount {
+            room_name,
+            user_count,
+        }) => {
+            let _ = gui_commands_transmitter
+                .send(ClientMessage::RoomUserCount {
+                    room_name,
+                    user_count,
+                })
+                .await;
+        }
+        ControlPayload::Room(RoomMessage::Roster { users }) => {
+            let _ = gui_commands_transmitter
+                .send(ClientMessage::RoomRoster { users })
+                .await;
+        }
+        ControlPayload::Room(RoomMessage::TxtMessage { body, user_id }) => {
+            let _ = gui_commands_transmitter
+                .send(ClientMessage::ChatMessage { user_id, body })
+                .await;
+        }
+        ControlPayload::Room(RoomMessage::AcceptUser { user }) => {
+            let _ = connection_events_transmitter
+                .send(ConnectionEvent::RoomMemberJoined {
+                    user_id: user.user_id,
+                })
+                .await;
+            let _ = gui_commands_transmitter
+                .send(ClientMessage::RoomMemberJoined { user })
+                .await;
+        }
+        ControlPayload::Room(RoomMessage::RemoveUser { user_id }) => {
+            let _ = connection_events_transmitter
+                .send(ConnectionEvent::RoomMemberLeft { user_id })
+                .await;
+            let _ = gui_commands_transmitter
+                .send(ClientMessage::RoomMemberLeft { user_id })
+                .await;
+        }
+        ControlPayload::Room(RoomMessage::VoiceState {
+            user_id,
+            muted,
+            deafened,
+        }) => {
+            let _ = gui_commands_transmitter
+                .send(ClientMessage::MemberVoiceState {
+                    user_id,
+                    muted,
+                    deafened,
+                })
+                .await;
+        }
+        other => println!("Unexpected control message: {:?}", other),
+    }
+}
+
+async fn next_room_list_poll(room_list_poll: &mut Option<Interval>) {
+    match room_list_poll {
+        Some(room_list_poll) => {
+            room_list_poll.tick().await;
+        }
+        None => pending().await,
+    }
+}
+
+async fn next_voice_message(voice_output_opt: &mut Option<VoiceOutput>) -> VoiceMessage {
+    match voice_output_opt {
+        Some(voice_output) => match voice_output.next_control_message().await {
+            Some(message) => message,
+            None => pending().await,
+        },
+        None => pending().await,
     }
 }
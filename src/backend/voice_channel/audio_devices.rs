@@ -0,0 +1,126 @@
+use std::{error::Error, str::FromStr};
+
+use cpal::{
+    DeviceId, SampleFormat, SupportedStreamConfig, SupportedStreamConfigRange,
+    traits::{DeviceTrait, HostTrait},
+};
+
+use crate::messages::audio_device::{AudioDeviceInfo, AudioHostDevices};
+
+pub fn list_audio_devices() -> Vec<AudioHostDevices> {
+    let mut hosts = Vec::new();
+    for host_id in cpal::available_hosts() {
+        let Ok(host) = cpal::host_from_id(host_id) else {
+            continue;
+        };
+        let input_devices = match host.input_devices() {
+            Ok(devices) => devices.filter_map(|device| device_info(&device)).collect(),
+            Err(_) => Vec::new(),
+        };
+        let output_devices = match host.output_devices() {
+            Ok(devices) => devices.filter_map(|device| device_info(&device)).collect(),
+            Err(_) => Vec::new(),
+        };
+        hosts.push(AudioHostDevices {
+            host_name: host_id.name().to_string(),
+            input_devices,
+            output_devices,
+        });
+    }
+    hosts
+}
+
+/// Returns the selected input device, or the default one if nothing is selected or the
+/// selected device is gone.
+pub fn input_device(device_id: Option<&str>) -> Result<cpal::Device, Box<dyn Error>> {
+    if let Some(device) = device_id.and_then(find_device) {
+        return Ok(device);
+    }
+    cpal::default_host()
+        .default_input_device()
+        .ok_or_else(|| "No input device found".into())
+}
+
+/// Returns the selected output device, or the default one if nothing is selected or the
+/// selected device is gone.
+pub fn output_device(device_id: Option<&str>) -> Result<cpal::Device, Box<dyn Error>> {
+    if let Some(device) = device_id.and_then(find_device) {
+        return Ok(device);
+    }
+    cpal::default_host()
+        .default_output_device()
+        .ok_or_else(|| "No output device found".into())
+}
+
+/// Picks the input config closest to the Opus pipeline format that the device can open natively.
+pub fn input_stream_config(
+    device: &cpal::Device,
+    sample_rate: u32,
+) -> Result<SupportedStreamConfig, Box<dyn Error>> {
+    let ranges = device.supported_input_configs()?;
+    match best_stream_config(ranges, sample_rate) {
+        Some(config) => Ok(config),
+        None => Ok(device.default_input_config()?),
+    }
+}
+
+/// Picks the output config closest to the Opus pipeline format that the device can open natively.
+pub fn output_stream_config(
+    device: &cpal::Device,
+    sample_rate: u32,
+) -> Result<SupportedStreamConfig, Box<dyn Error>> {
+    let ranges = device.supported_output_configs()?;
+    match best_stream_config(ranges, sample_rate) {
+        Some(config) => Ok(config),
+        None => Ok(device.default_output_config()?),
+    }
+}
+
+/// Prefers the pipeline sample rate, then stereo, then `f32` samples, so that as little
+/// conversion as possible is needed.
+fn best_stream_config(
+    ranges: impl Iterator<Item = SupportedStreamConfigRange>,
+    sample_rate: u32,
+) -> Option<SupportedStreamConfig> {
+    ranges
+        .filter(|range| is_supported_sample_format(range.sample_format()))
+        .map(|range| {
+            let rate = sample_rate.clamp(range.min_sample_rate(), range.max_sample_rate());
+            range.with_sample_rate(rate)
+        })
+        .min_by_key(|config| {
+            (
+                config.sample_rate() != sample_rate,
+                config.channels() != 2,
+                config.sample_format() != SampleFormat::F32,
+                config.sample_rate().abs_diff(sample_rate),
+            )
+        })
+}
+
+fn is_supported_sample_format(sample_format: SampleFormat) -> bool {
+    matches!(
+        sample_format,
+        SampleFormat::F32 | SampleFormat::I16 | SampleFormat::U16 | SampleFormat::I32
+    )
+}
+
+fn find_device(device_id: &str) -> Option<cpal::Device> {
+    let device_id = DeviceId::from_str(device_id).ok()?;
+    let host = cpal::host_from_id(device_id.0).ok()?;
+    let device = host.device_by_id(&device_id);
+    if device.is_none() {
+        println!(
+            "Audio device {} not found, using the default one",
+            device_id
+        );
+    }
+    device
+}
+
+fn device_info(device: &cpal::Device) -> Option<AudioDeviceInfo> {
+    Some(AudioDeviceInfo {
+        id: device.id().ok()?.to_string(),
+        name: device.description().ok()?.name().to_string(),
+    })
+}
@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use wtransport::Connection;
+
+const MIN_BITRATE_KBPS: u32 = 8;
+/// Loss above this or a round trip above `HIGH_RTT` lowers the bitrate.
+const HIGH_LOSS_PERCENT: u8 = 10;
+const HIGH_RTT: Duration = Duration::from_millis(400);
+/// Below both of these the bitrate recovers towards the configured one.
+const LOW_LOSS_PERCENT: u8 = 2;
+const LOW_RTT: Duration = Duration::from_millis(200);
+
+/// Follows the packet loss and round trip time of the QUIC connection and derives the Opus
+/// loss hint and bitrate from them.
+pub struct BitrateAdapter {
+    configured_kbps: u32,
+    current_kbps: u32,
+    last_sent_packets: u64,
+    last_lost_packets: u64,
+}
+
+impl BitrateAdapter {
+    pub fn new(configured_kbps: u32) -> Self {
+        Self {
+            configured_kbps,
+            current_kbps: configured_kbps,
+            last_sent_packets: 0,
+            last_lost_packets: 0,
+        }
+    }
+
+    pub fn set_configured_kbps(&mut self, configured_kbps: u32) {
+        self.configured_kbps = configured_kbps;
+        self.current_kbps = configured_kbps;
+    }
+
+    /// Returns the loss percentage since the last update and the bitrate to use now.
+    pub fn update(&mut self, connection: &Connection, adaptive: bool) -> (u8, u32) {
+        let path = connection.quic_connection().stats().path;
+        let sent_packets = path.sent_packets.saturating_sub(self.last_sent_packets);
+        let lost_packets = path.lost_packets.saturating_sub(self.last_lost_packets);
+        self.last_sent_packets = path.sent_packets;
+        self.last_lost_packets = path.lost_packets;
+        let loss_percent = match sent_packets {
+            0 => 0,
+            _ => (lost_packets * 100 / sent_packets).min(100) as u8,
+        };
+
+        let rtt = connection.rtt();
+        if !adaptive {
+            self.current_kbps = self.configured_kbps;
+        } else if loss_percent > HIGH_LOSS_PERCENT || rtt > HIGH_RTT {
+            self.current_kbps = (self.current_kbps * 3 / 4).max(MIN_BITRATE_KBPS);
+        } else if loss_percent < LOW_LOSS_PERCENT && rtt < LOW_RTT {
+            self.current_kbps =
+                (self.current_kbps + self.current_kbps / 10 + 1).min(self.configured_kbps);
+        }
+        (loss_percent, self.current_kbps)
+    }
+}
@@ -0,0 +1,135 @@
+use super::voice_activity::frame_level_db;
+
+/// Level the automatic gain control steers speech towards, in dBFS.
+const AGC_TARGET_DB: f32 = -20.0;
+/// Speech frames quieter than this don't move the gain, on top of the voice activity check.
+const AGC_NOISE_FLOOR_DB: f32 = -55.0;
+const AGC_MAX_GAIN_DB: f32 = 20.0;
+const AGC_MIN_GAIN_DB: f32 = -20.0;
+/// Per-frame adjustment towards the target, the gain backs off faster than it rises.
+const AGC_ATTACK_DB: f32 = 1.0;
+const AGC_RELEASE_DB: f32 = 0.1;
+
+/// Fixed input gain followed by an optional slow automatic gain control.
+pub struct GainControl {
+    gain: f32,
+    automatic: bool,
+    automatic_gain_db: f32,
+}
+
+impl GainControl {
+    pub fn new(gain: f32, automatic: bool) -> Self {
+        Self {
+            gain,
+            automatic,
+            automatic_gain_db: 0.0,
+        }
+    }
+
+    pub fn set(&mut self, gain: f32, automatic: bool) {
+        self.gain = gain;
+        if !automatic {
+            self.automatic_gain_db = 0.0;
+        }
+        self.automatic = automatic;
+    }
+
+    /// Applies the fixed input gain to one frame in place. The voice activity detector and the
+    /// level meter look at the frame after this, before the automatic gain.
+    pub fn apply_input_gain(&self, samples: &mut [f32]) {
+        apply(samples, self.gain);
+    }
+
+    /// Applies the automatic gain to a frame that already went through the input gain. It only
+    /// adapts on frames the voice activity detector took for speech, so pauses and background
+    /// noise never pull the gain up.
+    pub fn apply_automatic_gain(&mut self, samples: &mut [f32], speech: bool) {
+        if !self.automatic {
+            return;
+        }
+        let level_db = frame_level_db(samples);
+        if speech && level_db > AGC_NOISE_FLOOR_DB {
+            let error_db = AGC_TARGET_DB - (level_db + self.automatic_gain_db);
+            let step_db = error_db.clamp(-AGC_ATTACK_DB, AGC_RELEASE_DB);
+            self.automatic_gain_db =
+                (self.automatic_gain_db + step_db).clamp(AGC_MIN_GAIN_DB, AGC_MAX_GAIN_DB);
+        }
+        apply(samples, 10.0f32.powf(self.automatic_gain_db / 20.0));
+    }
+}
+
+fn apply(samples: &mut [f32], gain: f32) {
+    if gain == 1.0 {
+        return;
+    }
+    for sample in samples {
+        *sample = (*sample * gain).clamp(-1.0, 1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_SAMPLES: usize = 960;
+
+    /// A frame at `level_db` dBFS RMS.
+    fn frame(level_db: f32) -> Vec<f32> {
+        vec![10.0f32.powf(level_db / 20.0); FRAME_SAMPLES]
+    }
+
+    #[test]
+    fn input_gain_scales_and_clamps() {
+        let gain_control = GainControl::new(2.0, false);
+        let mut samples = vec![0.25, -0.75];
+        gain_control.apply_input_gain(&mut samples);
+        assert_eq!(samples, vec![0.5, -1.0]);
+    }
+
+    #[test]
+    fn quiet_speech_is_raised_slowly() {
+        let mut gain_control = GainControl::new(1.0, true);
+        for _ in 0..10 {
+            gain_control.apply_automatic_gain(&mut frame(-40.0), true);
+        }
+        assert!((gain_control.automatic_gain_db - 10.0 * AGC_RELEASE_DB).abs() < 1e-4);
+    }
+
+    #[test]
+    fn loud_speech_is_lowered_quickly() {
+        let mut gain_control = GainControl::new(1.0, true);
+        let mut samples = frame(-5.0);
+        gain_control.apply_automatic_gain(&mut samples, true);
+        assert_eq!(gain_control.automatic_gain_db, -AGC_ATTACK_DB);
+        assert!(frame_level_db(&samples) < -5.0);
+    }
+
+    #[test]
+    fn noise_doesnt_move_the_gain() {
+        let mut gain_control = GainControl::new(1.0, true);
+        for _ in 0..100 {
+            gain_control.apply_automatic_gain(&mut frame(-52.0), false);
+            gain_control.apply_automatic_gain(&mut frame(-70.0), true);
+        }
+        assert_eq!(gain_control.automatic_gain_db, 0.0);
+    }
+
+    #[test]
+    fn gain_stays_within_its_limits() {
+        let mut gain_control = GainControl::new(1.0, true);
+        for _ in 0..1000 {
+            gain_control.apply_automatic_gain(&mut frame(-50.0), true);
+        }
+        assert_eq!(gain_control.automatic_gain_db, AGC_MAX_GAIN_DB);
+    }
+
+    #[test]
+    fn disabling_resets_the_automatic_gain() {
+        let mut gain_control = GainControl::new(1.0, true);
+        gain_control.apply_automatic_gain(&mut frame(-5.0), true);
+        gain_control.set(1.0, false);
+        let mut samples = frame(-5.0);
+        gain_control.apply_automatic_gain(&mut samples, true);
+        assert_eq!(samples, frame(-5.0));
+    }
+}
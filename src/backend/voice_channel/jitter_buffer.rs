@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+
+use tokio::time::Instant;
+
+const FRAME_DURATION_SECS: f32 = 0.02;
+const MIN_DEPTH: usize = 1;
+/// FEC rebuilds a lost frame from the packet after it, which has to be buffered already.
+const MIN_DEPTH_WITH_FEC: usize = 2;
+const MAX_DEPTH: usize = 10;
+/// Frames the buffer may run ahead of its target before the oldest ones are dropped.
+const MAX_EXCESS_FRAMES: usize = 3;
+/// Longer gaps aren't concealed frame by frame, playout skips to the next packet instead.
+const MAX_CONCEALED_FRAMES: u64 = 5;
+
+pub enum Playout {
+    Packet(Vec<u8>),
+    /// The next packet is lost or late while later ones have arrived. Carries a copy of the
+    /// packet right after it if that one is here, its in-band FEC data can rebuild the lost one.
+    Missing {
+        following_packet: Option<Vec<u8>>,
+    },
+    /// Nothing to play, either between talk spurts or while buffering a new one.
+    Empty,
+}
+
+/// Per-user adaptive jitter buffer. Packets are reordered by sequence number and released one
+/// 20 ms frame at a time. Every talk spurt starts once the target depth is buffered, the
+/// target follows the interarrival jitter estimated as in RFC 3550.
+pub struct JitterBuffer {
+    packets: BTreeMap<u64, Vec<u8>>,
+    next_sequence: Option<u64>,
+    last_arrival: Option<(u64, Instant)>,
+    jitter_secs: f32,
+    min_depth: usize,
+    target_depth: usize,
+}
+
+impl JitterBuffer {
+    pub fn new(forward_error_correction: bool) -> Self {
+        let min_depth = min_depth(forward_error_correction);
+        Self {
+            packets: BTreeMap::new(),
+            next_sequence: None,
+            last_arrival: None,
+            jitter_secs: 0.0,
+            min_depth,
+            target_depth: min_depth + 1,
+        }
+    }
+
+    /// Takes effect with the next talk spurt, like any other change of the target depth.
+    pub fn set_forward_error_correction(&mut self, forward_error_correction: bool) {
+        self.min_depth = min_depth(forward_error_correction);
+        self.target_depth = self.target_depth.max(self.min_depth);
+    }
+
+    pub fn insert(&mut self, sequence: u64, body: Vec<u8>) {
+        let now = Instant::now();
+        match self.last_arrival {
+            Some((last_sequence, last_arrival)) if sequence > last_sequence => {
+                let expected = (sequence - last_sequence) as f32 * FRAME_DURATION_SECS;
+                let actual = now.duration_since(last_arrival).as_secs_f32();
+                self.jitter_secs += ((actual - expected).abs() - self.jitter_secs) / 16.0;
+                self.last_arrival = Some((sequence, now));
+            }
+            Some(_) => {}
+            None => self.last_arrival = Some((sequence, now)),
+        }
+
+        if self
+            .next_sequence
+            .is_some_and(|next_sequence| sequence < next_sequence)
+        {
+            return;
+        }
+        self.packets.insert(sequence, body);
+    }
+
+    /// Takes the next frame to play.
+    pub fn pop(&mut self) -> Playout {
+        let mut next_sequence = match self.next_sequence {
+            Some(next_sequence) => next_sequence,
+            None => {
+                if self.packets.len() < self.target_depth {
+                    return Playout::Empty;
+                }
+                match self.packets.keys().next() {
+                    Some(first_sequence) => *first_sequence,
+                    None => return Playout::Empty,
+                }
+            }
+        };
+
+        // A burst or a slower sender clock piles up latency, catch up by skipping ahead.
+        if self.packets.len() > self.target_depth + MAX_EXCESS_FRAMES {
+            while self.packets.len() > self.target_depth {
+                self.packets.pop_first();
+            }
+            if let Some(first_sequence) = self.packets.keys().next() {
+                next_sequence = next_sequence.max(*first_sequence);
+            }
+        }
+
+        if let Some(body) = self.packets.remove(&next_sequence) {
+            self.next_sequence = Some(next_sequence + 1);
+            return Playout::Packet(body);
+        }
+        match self.packets.keys().next().copied() {
+            None => {
+                // Underrun: the talk spurt ended or the network stalled, buffer up again.
+                self.next_sequence = None;
+                self.last_arrival = None;
+                self.adapt_target_depth();
+                Playout::Empty
+            }
+            Some(first_sequence) if first_sequence - next_sequence > MAX_CONCEALED_FRAMES => {
+                self.next_sequence = Some(first_sequence);
+                self.pop()
+            }
+            Some(_) => {
+                self.next_sequence = Some(next_sequence + 1);
+                Playout::Missing {
+                    following_packet: self.packets.get(&(next_sequence + 1)).cloned(),
+                }
+            }
+        }
+    }
+
+    fn adapt_target_depth(&mut self) {
+        let jitter_frames = (3.0 * self.jitter_secs / FRAME_DURATION_SECS).ceil() as usize;
+        self.target_depth = (1 + jitter_frames).clamp(self.min_depth, MAX_DEPTH);
+    }
+}
+
+fn min_depth(forward_error_correction: bool) -> usize {
+    if forward_error_correction {
+        MIN_DEPTH_WITH_FEC
+    } else {
+        MIN_DEPTH
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(buffer: &mut JitterBuffer, sequences: impl IntoIterator<Item = u64>) {
+        for sequence in sequences {
+            buffer.insert(sequence, vec![sequence as u8]);
+        }
+    }
+
+    fn played(playout: Playout) -> Option<u8> {
+        match playout {
+            Playout::Packet(body) => Some(body[0]),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn reorders_packets() {
+        let mut buffer = JitterBuffer::new(false);
+        fill(&mut buffer, [2, 0, 1]);
+        assert_eq!(played(buffer.pop()), Some(0));
+        assert_eq!(played(buffer.pop()), Some(1));
+        assert_eq!(played(buffer.pop()), Some(2));
+    }
+
+    #[test]
+    fn single_gap_is_missing_with_following_packet() {
+        let mut buffer = JitterBuffer::new(false);
+        fill(&mut buffer, [0, 2, 3]);
+        assert_eq!(played(buffer.pop()), Some(0));
+        match buffer.pop() {
+            Playout::Missing { following_packet } => assert_eq!(following_packet, Some(vec![2])),
+            _ => panic!("expected the lost packet to be reported missing"),
+        }
+        assert_eq!(played(buffer.pop()), Some(2));
+    }
+
+    #[test]
+    fn long_gap_skips_ahead() {
+        let mut buffer = JitterBuffer::new(false);
+        let after_gap = MAX_CONCEALED_FRAMES + 2;
+        fill(&mut buffer, [0, after_gap]);
+        assert_eq!(played(buffer.pop()), Some(0));
+        assert_eq!(played(buffer.pop()), Some(after_gap as u8));
+    }
+
+    #[test]
+    fn underrun_restarts_at_any_sequence() {
+        let mut buffer = JitterBuffer::new(false);
+        fill(&mut buffer, [10, 11]);
+        assert_eq!(played(buffer.pop()), Some(10));
+        assert_eq!(played(buffer.pop()), Some(11));
+        assert!(matches!(buffer.pop(), Playout::Empty));
+        assert_eq!(buffer.next_sequence, None);
+
+        // A restarted sender counts from zero again.
+        let target_depth = buffer.target_depth as u64;
+        fill(&mut buffer, 0..target_depth);
+        assert_eq!(played(buffer.pop()), Some(0));
+    }
+
+    #[test]
+    fn excess_depth_catches_up() {
+        let mut buffer = JitterBuffer::new(false);
+        let depth = buffer.target_depth + MAX_EXCESS_FRAMES + 1;
+        fill(&mut buffer, 0..depth as u64);
+        let first_kept = depth - buffer.target_depth;
+        assert_eq!(played(buffer.pop()), Some(first_kept as u8));
+        assert_eq!(buffer.packets.len(), buffer.target_depth - 1);
+    }
+
+    #[test]
+    fn fec_buffers_at_least_two_packets() {
+        let mut buffer = JitterBuffer::new(true);
+        fill(&mut buffer, [0, 1]);
+        assert!(matches!(buffer.pop(), Playout::Empty));
+        fill(&mut buffer, [2]);
+        assert_eq!(played(buffer.pop()), Some(0));
+        assert_eq!(played(buffer.pop()), Some(1));
+        assert_eq!(played(buffer.pop()), Some(2));
+
+        // Adapting to the measured jitter doesn't go below the FEC minimum either.
+        assert!(matches!(buffer.pop(), Playout::Empty));
+        assert!(buffer.target_depth >= MIN_DEPTH_WITH_FEC);
+    }
+
+    #[test]
+    fn loss_is_recovered_from_the_following_packet() {
+        let mut buffer = JitterBuffer::new(true);
+        fill(&mut buffer, [0, 1, 3, 4]);
+        assert_eq!(played(buffer.pop()), Some(0));
+        assert_eq!(played(buffer.pop()), Some(1));
+        match buffer.pop() {
+            Playout::Missing {
+                following_packet: Some(following_packet),
+            } => assert_eq!(following_packet, vec![3]),
+            _ => panic!("expected packet 2 to be recoverable from packet 3"),
+        }
+        // The packet used for recovery still plays in its own slot.
+        assert_eq!(played(buffer.pop()), Some(3));
+        assert_eq!(played(buffer.pop()), Some(4));
+    }
+}
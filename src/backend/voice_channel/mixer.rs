@@ -0,0 +1,296 @@
+use crossbeam_channel::{Receiver, Sender, TrySendError, bounded};
+use ringbuf::HeapCons;
+use ringbuf::traits::{Consumer, Observer};
+
+use super::resampler::StereoFrame;
+
+const CHANNELS: usize = 2;
+/// Frames mixed at once, 5 ms at 48 kHz.
+const BLOCK_FRAMES: usize = 240;
+/// Sources beyond this are refused, the source list never grows inside the callback.
+const MAX_SOURCES: usize = 64;
+/// Per-user gains and mutes the mixer keeps, including for users it has no source for yet.
+const MAX_USER_GAINS: usize = 256;
+const COMMAND_CAPACITY: usize = 256;
+/// The limiter pulls the master bus below this level, about -1 dBFS.
+const LIMITER_THRESHOLD: f32 = 0.9;
+/// Per-frame step back towards unity gain, recovers from a peak in roughly 100 ms.
+const LIMITER_RELEASE: f32 = 0.0002;
+
+pub enum MixerCommand {
+    AddSource {
+        user_id: u64,
+        consumer: HeapCons<f32>,
+    },
+    SetGain {
+        user_id: u64,
+        gain: f32,
+    },
+    SetMuted {
+        user_id: u64,
+        muted: bool,
+    },
+    SetMasterGain {
+        gain: f32,
+    },
+    /// Keeps consuming every source but outputs silence.
+    SetDeafened {
+        deafened: bool,
+    },
+}
+
+struct MixerSource {
+    user_id: u64,
+    consumer: HeapCons<f32>,
+    /// The user's gain, zero while they are muted.
+    gain: f32,
+}
+
+#[derive(Clone, Copy)]
+struct UserGain {
+    user_id: u64,
+    gain: f32,
+    muted: bool,
+}
+
+impl UserGain {
+    fn new(user_id: u64) -> Self {
+        Self {
+            user_id,
+            gain: 1.0,
+            muted: false,
+        }
+    }
+
+    fn effective_gain(&self) -> f32 {
+        if self.muted { 0.0 } else { self.gain }
+    }
+}
+
+/// Control side of the mixer, kept by `VoiceOutput`.
+pub struct MixerHandle {
+    command_sender: Sender<MixerCommand>,
+    retired_receiver: Receiver<HeapCons<f32>>,
+}
+
+impl MixerHandle {
+    pub fn send(&self, command: MixerCommand) {
+        if let Err(TrySendError::Full(_)) = self.command_sender.try_send(command) {
+            eprintln!("Mixer command queue is full, dropping a command");
+        }
+    }
+
+    /// Frees the ring buffers of sources the mixer let go of, outside the audio callback.
+    pub fn release_retired_sources(&self) {
+        while self.retired_receiver.try_recv().is_ok() {}
+    }
+}
+
+/// Sums every user's decoded audio at the 48 kHz stereo pipeline format. Runs inside the
+/// output callback, so everything it needs is allocated up front and freed elsewhere.
+/// Commands are applied at the start of every block, so they take effect within 5 ms.
+pub struct Mixer {
+    command_receiver: Receiver<MixerCommand>,
+    retired_sender: Sender<HeapCons<f32>>,
+    sources: Vec<MixerSource>,
+    user_gains: Vec<UserGain>,
+    source_block: Vec<f32>,
+    mix_block: Vec<f32>,
+    block_position: usize,
+    master_gain: f32,
+    deafened: bool,
+    limiter_gain: f32,
+}
+
+impl Mixer {
+    pub fn new(master_gain: f32) -> (Self, MixerHandle) {
+        let (command_sender, command_receiver) = bounded(COMMAND_CAPACITY);
+        let (retired_sender, retired_receiver) = bounded(MAX_SOURCES);
+        let mixer = Self {
+            command_receiver,
+            retired_sender,
+            sources: Vec::with_capacity(MAX_SOURCES),
+            user_gains: Vec::with_capacity(MAX_USER_GAINS),
+            source_block: vec![0.0; BLOCK_FRAMES * CHANNELS],
+            mix_block: vec![0.0; BLOCK_FRAMES * CHANNELS],
+            block_position: BLOCK_FRAMES,
+            master_gain,
+            deafened: false,
+            limiter_gain: 1.0,
+        };
+        let handle = MixerHandle {
+            command_sender,
+            retired_receiver,
+        };
+        (mixer, handle)
+    }
+
+    pub fn next_frame(&mut self) -> StereoFrame {
+        if self.block_position == BLOCK_FRAMES {
+            self.mix_next_block();
+            self.block_position = 0;
+        }
+        let index = self.block_position * CHANNELS;
+        self.block_position += 1;
+        [self.mix_block[index], self.mix_block[index + 1]]
+    }
+
+    fn mix_next_block(&mut self) {
+        self.apply_commands();
+        // A dropped producer means the user left or the stream ended, play out what's left.
+        let mut index = 0;
+        while index < self.sources.len() {
+            let source = &self.sources[index];
+            if source.consumer.write_is_held() || !source.consumer.is_empty() {
+                index += 1;
+            } else {
+                let source = self.sources.swap_remove(index);
+                self.retire(source.consumer);
+            }
+        }
+
+        self.mix_block.fill(0.0);
+        for source in &mut self.sources {
+            let popped = source.consumer.pop_slice(&mut self.source_block);
+            // Sources keep draining while deafened so nothing stale plays afterwards.
+            if self.deafened {
+                continue;
+            }
+            let gain = source.gain * self.master_gain;
+            for (mixed, sample) in self.mix_block.iter_mut().zip(&self.source_block[..popped]) {
+                *mixed += sample * gain;
+            }
+        }
+        self.limit();
+    }
+
+    fn apply_commands(&mut self) {
+        while let Ok(command) = self.command_receiver.try_recv() {
+            match command {
+                MixerCommand::AddSource { user_id, consumer } => {
+                    if self.sources.len() < MAX_SOURCES {
+                        let gain = self.user_gain(user_id).effective_gain();
+                        self.sources.push(MixerSource {
+                            user_id,
+                            consumer,
+                            gain,
+                        });
+                    } else {
+                        self.retire(consumer);
+                    }
+                }
+                MixerCommand::SetGain { user_id, gain } => {
+                    let mut user_gain = self.user_gain(user_id);
+                    user_gain.gain = gain;
+                    self.set_user_gain(user_gain);
+                }
+                MixerCommand::SetMuted { user_id, muted } => {
+                    let mut user_gain = self.user_gain(user_id);
+                    user_gain.muted = muted;
+                    self.set_user_gain(user_gain);
+                }
+                MixerCommand::SetMasterGain { gain } => self.master_gain = gain,
+                MixerCommand::SetDeafened { deafened } => self.deafened = deafened,
+            }
+        }
+    }
+
+    fn user_gain(&self, user_id: u64) -> UserGain {
+        self.user_gains
+            .iter()
+            .find(|user_gain| user_gain.user_id == user_id)
+            .copied()
+            .unwrap_or_else(|| UserGain::new(user_id))
+    }
+
+    fn set_user_gain(&mut self, user_gain: UserGain) {
+        if let Some(stored) = self
+            .user_gains
+            .iter_mut()
+            .find(|stored| stored.user_id == user_gain.user_id)
+        {
+            *stored = user_gain;
+        } else if self.user_gains.len() < MAX_USER_GAINS {
+            self.user_gains.push(user_gain);
+        }
+        for source in &mut self.sources {
+            if source.user_id == user_gain.user_id {
+                source.gain = user_gain.effective_gain();
+            }
+        }
+    }
+
+    /// Soft limiter on the master bus: the gain drops instantly to keep peaks under the
+    /// threshold and recovers slowly, instead of hard clipping every overshooting sample.
+    fn limit(&mut self) {
+        for frame in self.mix_block.chunks_exact_mut(CHANNELS) {
+            let peak = frame[0].abs().max(frame[1].abs());
+            let target_gain = if peak > LIMITER_THRESHOLD {
+                LIMITER_THRESHOLD / peak
+            } else {
+                1.0
+            };
+            if target_gain < self.limiter_gain {
+                self.limiter_gain = target_gain;
+            } else {
+                self.limiter_gain += (target_gain - self.limiter_gain) * LIMITER_RELEASE;
+            }
+            for sample in frame {
+                *sample = (*sample * self.limiter_gain).clamp(-1.0, 1.0);
+            }
+        }
+    }
+
+    /// Hands a ring buffer back to the control side to be freed there. Only if that queue is
+    /// full does the buffer get freed in the callback.
+    fn retire(&self, consumer: HeapCons<f32>) {
+        let _ = self.retired_sender.try_send(consumer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limited_block(mixer: &mut Mixer, level: f32) -> Vec<f32> {
+        mixer.mix_block.fill(level);
+        mixer.limit();
+        mixer.mix_block.clone()
+    }
+
+    #[test]
+    fn quiet_audio_passes_unchanged() {
+        let (mut mixer, _handle) = Mixer::new(1.0);
+        let block = limited_block(&mut mixer, 0.5);
+        assert!(block.iter().all(|sample| *sample == 0.5));
+    }
+
+    #[test]
+    fn peaks_stay_under_the_threshold() {
+        let (mut mixer, _handle) = Mixer::new(1.0);
+        let block = limited_block(&mut mixer, 3.0);
+        assert!(
+            block
+                .iter()
+                .all(|sample| (sample.abs() - LIMITER_THRESHOLD).abs() < 1e-6)
+        );
+        let block = limited_block(&mut mixer, -3.0);
+        assert!(block.iter().all(|sample| sample.abs() <= LIMITER_THRESHOLD));
+    }
+
+    #[test]
+    fn gain_recovers_slowly_after_a_peak() {
+        let (mut mixer, _handle) = Mixer::new(1.0);
+        limited_block(&mut mixer, 1.8);
+        let reduced_gain = mixer.limiter_gain;
+        assert!((reduced_gain - 0.5).abs() < 1e-6);
+
+        let block = limited_block(&mut mixer, 0.1);
+        assert!(block.iter().all(|sample| *sample < 0.1));
+        assert!(mixer.limiter_gain > reduced_gain);
+        for _ in 0..1000 {
+            limited_block(&mut mixer, 0.1);
+        }
+        assert!(mixer.limiter_gain > 0.99);
+    }
+}
@@ -1,2 +1,9 @@
+pub mod audio_devices;
+mod bitrate_adapter;
+mod gain_control;
+mod jitter_buffer;
+mod mixer;
+mod resampler;
+mod voice_activity;
 pub mod voice_input;
 pub mod voice_output;
@@ -0,0 +1,208 @@
+use cpal::{FromSample, Sample};
+
+/// One interleaved frame of the stereo Opus pipeline.
+pub type StereoFrame = [f32; 2];
+
+/// Anti-alias cutoff as a fraction of the output rate, just below its Nyquist frequency.
+const ANTI_ALIAS_CUTOFF: f64 = 0.45;
+
+/// Streaming linear-interpolation resampler between a device rate and the pipeline rate.
+/// When downsampling, the input is low-passed first so content above the output's Nyquist
+/// frequency doesn't fold back as aliasing. Linear interpolation keeps the callbacks cheap.
+pub struct Resampler {
+    step: f64,
+    position: f64,
+    previous: StereoFrame,
+    current: StereoFrame,
+    anti_alias: Option<[LowPass; 2]>,
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32) -> Self {
+        // Two cascaded biquads give a 24 dB per octave slope.
+        let anti_alias = (input_rate > output_rate).then(|| {
+            let cutoff = ANTI_ALIAS_CUTOFF * output_rate as f64 / input_rate as f64;
+            [LowPass::new(cutoff), LowPass::new(cutoff)]
+        });
+        Self {
+            step: input_rate as f64 / output_rate as f64,
+            position: 0.0,
+            previous: [0.0; 2],
+            current: [0.0; 2],
+            anti_alias,
+        }
+    }
+
+    /// Feeds one input frame and emits every output frame that falls before it.
+    pub fn push(&mut self, frame: StereoFrame, mut emit: impl FnMut(StereoFrame)) {
+        self.previous = self.current;
+        self.current = self.filter(frame);
+        while self.position < 1.0 {
+            emit(self.interpolate());
+            self.position += self.step;
+        }
+        self.position -= 1.0;
+    }
+
+    /// Produces one output frame, pulling as many input frames as it needs.
+    pub fn pull(&mut self, mut next: impl FnMut() -> StereoFrame) -> StereoFrame {
+        while self.position >= 1.0 {
+            self.previous = self.current;
+            let frame = next();
+            self.current = self.filter(frame);
+            self.position -= 1.0;
+        }
+        let frame = self.interpolate();
+        self.position += self.step;
+        frame
+    }
+
+    fn filter(&mut self, frame: StereoFrame) -> StereoFrame {
+        match &mut self.anti_alias {
+            Some(stages) => stages
+                .iter_mut()
+                .fold(frame, |frame, stage| stage.process(frame)),
+            None => frame,
+        }
+    }
+
+    fn interpolate(&self) -> StereoFrame {
+        let position = self.position as f32;
+        [
+            self.previous[0] + (self.current[0] - self.previous[0]) * position,
+            self.previous[1] + (self.current[1] - self.previous[1]) * position,
+        ]
+    }
+}
+
+/// Second-order Butterworth low-pass, both channels sharing the coefficients.
+struct LowPass {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    /// Transposed direct form II state per channel.
+    state: [[f32; 2]; 2],
+}
+
+impl LowPass {
+    /// `cutoff` is a fraction of the sample rate, below 0.5.
+    fn new(cutoff: f64) -> Self {
+        let omega = 2.0 * std::f64::consts::PI * cutoff;
+        let alpha = omega.sin() / std::f64::consts::SQRT_2;
+        let cos = omega.cos();
+        let a0 = 1.0 + alpha;
+        Self {
+            b0: ((1.0 - cos) / 2.0 / a0) as f32,
+            b1: ((1.0 - cos) / a0) as f32,
+            b2: ((1.0 - cos) / 2.0 / a0) as f32,
+            a1: (-2.0 * cos / a0) as f32,
+            a2: ((1.0 - alpha) / a0) as f32,
+            state: [[0.0; 2]; 2],
+        }
+    }
+
+    fn process(&mut self, frame: StereoFrame) -> StereoFrame {
+        let mut output = [0.0; 2];
+        for (channel, state) in self.state.iter_mut().enumerate() {
+            let input = frame[channel];
+            let filtered = self.b0 * input + state[0];
+            state[0] = self.b1 * input - self.a1 * filtered + state[1];
+            state[1] = self.b2 * input - self.a2 * filtered;
+            output[channel] = filtered;
+        }
+        output
+    }
+}
+
+/// Converts one device frame to stereo: mono is duplicated, extra channels are dropped.
+pub fn read_stereo_frame<T>(frame: &[T]) -> StereoFrame
+where
+    T: Sample,
+    f32: FromSample<T>,
+{
+    match frame {
+        [] => [0.0; 2],
+        [mono] => {
+            let mono = mono.to_sample::<f32>();
+            [mono, mono]
+        }
+        [left, right, ..] => [left.to_sample::<f32>(), right.to_sample::<f32>()],
+    }
+}
+
+/// Writes a stereo frame into one device frame: mono gets the average, extra channels silence.
+pub fn write_stereo_frame<T>(stereo: StereoFrame, frame: &mut [T])
+where
+    T: Sample + FromSample<f32>,
+{
+    match frame {
+        [] => {}
+        [mono] => *mono = T::from_sample((stereo[0] + stereo[1]) * 0.5),
+        [left, right, rest @ ..] => {
+            *left = T::from_sample(stereo[0]);
+            *right = T::from_sample(stereo[1]);
+            rest.fill(T::EQUILIBRIUM);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resample(input_rate: u32, output_rate: u32, input: &[StereoFrame]) -> Vec<StereoFrame> {
+        let mut resampler = Resampler::new(input_rate, output_rate);
+        let mut output = Vec::new();
+        for frame in input {
+            resampler.push(*frame, |frame| output.push(frame));
+        }
+        output
+    }
+
+    fn sine(rate: u32, frequency: f32, len: usize) -> Vec<StereoFrame> {
+        (0..len)
+            .map(|index| {
+                let sample = (std::f32::consts::TAU * frequency * index as f32 / rate as f32).sin();
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    fn peak(frames: &[StereoFrame]) -> f32 {
+        frames
+            .iter()
+            .flatten()
+            .fold(0.0, |peak, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn equal_rates_pass_through() {
+        let input = sine(48_000, 1_000.0, 480);
+        let output = resample(48_000, 48_000, &input);
+        // The output trails the input by one frame.
+        assert_eq!(output.len(), input.len());
+        assert_eq!(output[1..], input[..input.len() - 1]);
+    }
+
+    #[test]
+    fn output_length_follows_the_rate_ratio() {
+        let input = vec![[0.0; 2]; 44_100];
+        assert!(resample(44_100, 48_000, &input).len().abs_diff(48_000) <= 1);
+        let input = vec![[0.0; 2]; 48_000];
+        assert!(resample(48_000, 44_100, &input).len().abs_diff(44_100) <= 1);
+    }
+
+    #[test]
+    fn downsampling_attenuates_above_the_new_nyquist() {
+        // 23 kHz is representable at 48 kHz but would fold back to 21.1 kHz at 44.1 kHz.
+        let output = resample(48_000, 44_100, &sine(48_000, 23_000.0, 4_800));
+        let settled = &output[output.len() / 2..];
+        assert!(peak(settled) < 0.25, "peak {}", peak(settled));
+
+        let output = resample(48_000, 44_100, &sine(48_000, 1_000.0, 4_800));
+        let settled = &output[output.len() / 2..];
+        assert!(peak(settled) > 0.9, "peak {}", peak(settled));
+    }
+}
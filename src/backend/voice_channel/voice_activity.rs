@@ -0,0 +1,59 @@
+/// Number of 20 ms frames that keep counting as speech after the level drops, so pauses
+/// between words and quiet word endings aren't cut off.
+const HANGOVER_FRAMES: u32 = 15;
+/// Level reported for digital silence instead of negative infinity.
+pub const SILENCE_DB: f32 = -100.0;
+/// Level above which a voice counts as speaking in the member list, in dBFS.
+pub const SPEAKING_THRESHOLD_DB: f32 = -50.0;
+
+/// Energy-based voice activity detector with hangover.
+pub struct VoiceActivityDetector {
+    threshold_db: f32,
+    hangover_frames_left: u32,
+}
+
+impl VoiceActivityDetector {
+    pub fn new(threshold_db: f32) -> Self {
+        Self {
+            threshold_db,
+            hangover_frames_left: 0,
+        }
+    }
+
+    pub fn set_threshold_db(&mut self, threshold_db: f32) {
+        self.threshold_db = threshold_db;
+    }
+
+    /// Returns whether the frame should be treated as speech.
+    pub fn process(&mut self, samples: &[f32]) -> bool {
+        if frame_level_db(samples) >= self.threshold_db {
+            self.hangover_frames_left = HANGOVER_FRAMES;
+            true
+        } else if self.hangover_frames_left > 0 {
+            self.hangover_frames_left -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// RMS level of the frame in dBFS.
+pub fn frame_level_db(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return SILENCE_DB;
+    }
+    let mean_square =
+        samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32;
+    power_db(mean_square)
+}
+
+/// Converts a mean square sample value to dBFS.
+pub fn power_db(mean_square: f32) -> f32 {
+    (10.0 * mean_square.log10()).max(SILENCE_DB)
+}
+
+/// Converts a sample amplitude to dBFS.
+pub fn amplitude_db(amplitude: f32) -> f32 {
+    (20.0 * amplitude.log10()).max(SILENCE_DB)
+}
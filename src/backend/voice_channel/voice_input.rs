@@ -1,132 +1,448 @@
 use std::sync::Arc;
 
-use audiopus::{Application, Channels, SampleRate, coder::Encoder as OpusEncoder};
-use cpal::Stream;
+use audiopus::{Application, Bitrate, Channels, SampleRate, coder::Encoder as OpusEncoder};
+use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
 use ringbuf::storage::Heap;
 use ringbuf::wrap::caching::Caching;
-use std::u64;
-use tokio::sync::mpsc::Receiver;
-use tokio::sync::mpsc::error::TryRecvError;
+use tokio::select;
+use tokio::sync::Notify;
+use tokio::sync::mpsc::{Receiver, Sender};
 use wtransport::Connection;
 
+use super::audio_devices::{input_device, input_stream_config};
+use super::bitrate_adapter::BitrateAdapter;
+use super::gain_control::GainControl;
+use super::resampler::{Resampler, read_stereo_frame};
+use super::voice_activity::{SPEAKING_THRESHOLD_DB, VoiceActivityDetector, amplitude_db, power_db};
+use super::voice_output::activity_interval;
+use crate::messages::client_error::ClientErrorKind;
+use crate::messages::client_message::ClientMessage;
 use crate::messages::room_message::RoomMessage;
 use crate::messages::voice_message::VoiceMessage;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use crate::settings::voice_settings::{ChannelMode, EncoderSettings, TransmitMode, VoiceSettings};
+use cpal::traits::{DeviceTrait, StreamTrait};
 use ringbuf::traits::{Consumer, Observer, Producer, Split};
-use ringbuf::{HeapRb, SharedRb};
-use tokio::time::{Duration, sleep};
+use ringbuf::{HeapProd, HeapRb, SharedRb};
+use tokio::time::{Duration, Instant, MissedTickBehavior, interval};
 
 const SAMPLE_RATE: u32 = 48000;
-const CHANNELS: usize = 2;
 const FRAME_SIZE_MS: u32 = 20;
 const SAMPLES_PER_CHANNEL: usize = (SAMPLE_RATE as usize * FRAME_SIZE_MS as usize) / 1000;
-const TOTAL_SAMPLES_PER_FRAME: usize = SAMPLES_PER_CHANNEL * CHANNELS;
+/// Keeps sending after the push-to-talk key is released so word endings aren't clipped.
+const PUSH_TO_TALK_RELEASE_TAIL: Duration = Duration::from_millis(300);
+/// `OPUS_SET_DTX_REQUEST` from opus_defines.h, audiopus has no setter for it.
+const OPUS_SET_DTX_REQUEST: i32 = 4016;
+/// With DTX enabled, Opus encodes silence into packets of at most this many bytes.
+const DTX_PACKET_MAX_LEN: usize = 2;
+/// Frames summarised in one level meter update, 60 ms keeps the meter smooth enough.
+const LEVEL_REPORT_FRAMES: usize = 3;
+/// How often the loss hint and bitrate follow the connection statistics.
+const BITRATE_ADAPT_INTERVAL: Duration = Duration::from_secs(1);
+/// Loss hint used until the first connection statistics are in, makes FEC kick in right away.
+const INITIAL_PACKET_LOSS_PERCENT: u8 = 5;
+
+/// Where the encoded voice packets go.
+pub enum VoiceSink {
+    /// Send them to the room as datagrams.
+    Server(Arc<Connection>),
+    /// Hand them to a local decoder, for the mic test loopback.
+    Loopback(Sender<(Vec<u8>, u64)>),
+    /// Only measure the input level.
+    Discard,
+}
 
 pub struct VoiceInput {
     voice_input_control_receiver: Receiver<VoiceMessage>,
-    connection: Arc<Connection>,
+    sink: VoiceSink,
     encoder: OpusEncoder,
     consumer: Caching<Arc<SharedRb<Heap<f32>>>, false, true>,
+    frame_ready: Arc<Notify>,
     input_stream: Stream,
+    samples_per_frame: usize,
+    transmit_mode: TransmitMode,
+    voice_activity_detector: VoiceActivityDetector,
+    gain_control: GainControl,
+    encoder_settings: EncoderSettings,
+    bitrate_adapter: BitrateAdapter,
+    gui_commands_transmitter: Sender<ClientMessage>,
 }
 
 impl VoiceInput {
     pub fn new(
         voice_input_control_receiver: Receiver<VoiceMessage>,
-        connection_clone: Arc<Connection>,
+        sink: VoiceSink,
+        input_device_id: Option<&str>,
+        voice_settings: &VoiceSettings,
+        gui_commands_transmitter: Sender<ClientMessage>,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let encoder = OpusEncoder::new(SampleRate::Hz48000, Channels::Stereo, Application::Voip)?;
-        let host = cpal::default_host();
-        let input_device = host.default_input_device().expect("No input device found");
-        let config = cpal::StreamConfig {
-            channels: CHANNELS as u16,
-            sample_rate: SAMPLE_RATE,
-            buffer_size: cpal::BufferSize::Default,
+        let channel_mode = voice_settings.channel_mode;
+        let opus_channels = match channel_mode {
+            ChannelMode::Mono => Channels::Mono,
+            ChannelMode::Stereo => Channels::Stereo,
         };
-        let ring_buffer_len = SAMPLE_RATE as usize * CHANNELS;
+        let mut encoder = OpusEncoder::new(SampleRate::Hz48000, opus_channels, Application::Voip)?;
+        encoder.set_encoder_ctl_request(OPUS_SET_DTX_REQUEST, 1)?;
+        configure_encoder(&mut encoder, &voice_settings.encoder)?;
+        encoder.set_packet_loss_perc(INITIAL_PACKET_LOSS_PERCENT)?;
+        let input_device = input_device(input_device_id)?;
+        let supported_config = input_stream_config(&input_device, SAMPLE_RATE)?;
+        let config = supported_config.config();
+        let ring_buffer_len = SAMPLE_RATE as usize * channel_mode.channels();
         let ring = HeapRb::<f32>::new(ring_buffer_len);
-        let (mut producer, consumer) = ring.split();
-        let input_stream = input_device.build_input_stream(
-            &config,
-            move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                let _ = producer.push_slice(data);
-            },
-            move |err| eprintln!("Stream error: {}", err),
-            None,
-        )?;
+        let (producer, consumer) = ring.split();
+        let samples_per_frame = SAMPLES_PER_CHANNEL * channel_mode.channels();
+        let frame_ready = Arc::new(Notify::new());
+        let capture = Capture {
+            channel_mode,
+            producer,
+            samples_per_frame,
+            frame_ready: frame_ready.clone(),
+        };
+        let input_stream = match supported_config.sample_format() {
+            SampleFormat::F32 => build_capture_stream::<f32>(&input_device, &config, capture),
+            SampleFormat::I16 => build_capture_stream::<i16>(&input_device, &config, capture),
+            SampleFormat::U16 => build_capture_stream::<u16>(&input_device, &config, capture),
+            SampleFormat::I32 => build_capture_stream::<i32>(&input_device, &config, capture),
+            sample_format => {
+                return Err(format!("Unsupported input sample format {}", sample_format).into());
+            }
+        }?;
 
         Ok(Self {
             voice_input_control_receiver,
-            connection: connection_clone,
+            sink,
             encoder,
             consumer,
+            frame_ready,
             input_stream,
+            samples_per_frame,
+            transmit_mode: voice_settings.transmit_mode,
+            voice_activity_detector: VoiceActivityDetector::new(
+                voice_settings.voice_activity_threshold_db,
+            ),
+            gain_control: GainControl::new(
+                voice_settings.input_gain,
+                voice_settings.automatic_gain_control,
+            ),
+            encoder_settings: voice_settings.encoder,
+            bitrate_adapter: BitrateAdapter::new(voice_settings.encoder.bitrate_kbps),
+            gui_commands_transmitter,
         })
     }
 
-    pub fn run(mut self: Self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn run(mut self) -> Result<(), Box<dyn std::error::Error>> {
         self.input_stream.play()?;
         tokio::spawn(async move {
-            let _input_stream = self.input_stream;
+            let input_stream = self.input_stream;
             let mut sequence_number: u64 = 0;
-            let mut raw_samples = vec![0.0f32; TOTAL_SAMPLES_PER_FRAME];
+            let mut raw_samples = vec![0.0f32; self.samples_per_frame];
             let mut opus_output_buffer = [0u8; 1500];
-            loop {
-                if self.consumer.occupied_len() >= TOTAL_SAMPLES_PER_FRAME {
-                    self.consumer.pop_slice(&mut raw_samples);
-
-                    let opus_size = match self
-                        .encoder
-                        .encode_float(&raw_samples, &mut opus_output_buffer)
-                    {
-                        Ok(s) => s,
-                        Err(e) => {
-                            eprintln!("Encode error: {:?}", e);
-                            continue;
+            let mut push_to_talk_held = false;
+            let mut push_to_talk_released_at: Option<Instant> = None;
+            // Power of the speech sent since the last activity update, and the level the GUI
+            // shows for us, published like the levels of the other members.
+            let mut speaking_power = 0.0f32;
+            let mut speaking_samples = 0;
+            let mut speaking_level: Option<f32> = None;
+            let mut activity_interval = activity_interval();
+            let mut muted = false;
+            let mut level_meter = false;
+            let mut level_frames = 0;
+            let mut level_peak = 0.0f32;
+            let mut level_power = 0.0f32;
+            let mut bitrate_adapt_interval = interval(BITRATE_ADAPT_INTERVAL);
+            bitrate_adapt_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            let sending_to_server = matches!(self.sink, VoiceSink::Server(_));
+            'run: loop {
+                select! {
+                    message = self.voice_input_control_receiver.recv() => match message {
+                        Some(VoiceMessage::CloseVoiceInput {}) => break,
+                        Some(VoiceMessage::PushToTalk { pressed }) => {
+                            if push_to_talk_held && !pressed {
+                                push_to_talk_released_at = Some(Instant::now());
+                            }
+                            push_to_talk_held = pressed;
                         }
-                    };
-
-                    let packet = RoomMessage::VoicePacket {
-                        body: opus_output_buffer[0..opus_size].to_vec(),
-                        order_id: sequence_number,
-                        user_id: u64::MAX,
-                    };
-
-                    sequence_number = sequence_number.wrapping_add(1);
-
-                    match bincode::serialize(&packet) {
-                        Ok(serialized_data) => {
-                            match self.connection.send_datagram(serialized_data) {
-                                Ok(_) => {}
-                                Err(err) => {
-                                    println!("Error during sending voice datagram: {}", err);
-                                    break;
-                                }
+                        Some(VoiceMessage::SetVoiceActivityThreshold { threshold_db }) => {
+                            self.voice_activity_detector.set_threshold_db(threshold_db);
+                        }
+                        // Muted frames are dropped whatever the stream does, pausing the
+                        // capture on top also turns off the microphone indicator.
+                        Some(VoiceMessage::SetMuted { muted: true }) => {
+                            muted = true;
+                            if let Err(err) = input_stream.pause() {
+                                let _ = self
+                                    .gui_commands_transmitter
+                                    .send(ClientMessage::Error {
+                                        kind: ClientErrorKind::VoiceInput,
+                                        message: format!("Couldn't pause the microphone: {}", err),
+                                    })
+                                    .await;
+                            }
+                            self.consumer.clear();
+                            speaking_power = 0.0;
+                            speaking_samples = 0;
+                            if speaking_level.take().is_some() {
+                                let _ = self
+                                    .gui_commands_transmitter
+                                    .try_send(ClientMessage::LocalSpeaking { level_db: None });
+                            }
+                        }
+                        Some(VoiceMessage::SetMuted { muted: false }) => {
+                            muted = false;
+                            if let Err(err) = input_stream.play() {
+                                let _ = self
+                                    .gui_commands_transmitter
+                                    .send(ClientMessage::Error {
+                                        kind: ClientErrorKind::VoiceInput,
+                                        message: format!("Couldn't resume the microphone: {}", err),
+                                    })
+                                    .await;
+                            }
+                        }
+                        Some(VoiceMessage::SetLevelMeter { enabled }) => level_meter = enabled,
+                        Some(VoiceMessage::SetEncoderSettings { encoder_settings }) => {
+                            if let Err(err) =
+                                configure_encoder(&mut self.encoder, &encoder_settings)
+                            {
+                                println!("Error during configuring the encoder: {}", err);
                             }
+                            self.bitrate_adapter
+                                .set_configured_kbps(encoder_settings.bitrate_kbps);
+                            self.encoder_settings = encoder_settings;
                         }
-                        Err(err) => {
-                            println!("Error during serializing voice datagram: {}", err);
+                        Some(VoiceMessage::SetInputGain { gain, automatic }) => {
+                            self.gain_control.set(gain, automatic);
+                        }
+                        Some(_) => {}
+                        None => {
+                            println!("Voice input channel closed");
                             break;
                         }
+                    },
+                    _ = bitrate_adapt_interval.tick(), if sending_to_server => {
+                        if let VoiceSink::Server(connection) = &self.sink {
+                            let (loss_percent, bitrate_kbps) = self
+                                .bitrate_adapter
+                                .update(connection, self.encoder_settings.adaptive_bitrate);
+                            if let Err(err) =
+                                adapt_encoder(&mut self.encoder, loss_percent, bitrate_kbps)
+                            {
+                                println!("Error during adapting the bitrate: {}", err);
+                            }
+                        }
                     }
+                    _ = activity_interval.tick() => {
+                        let level_db = match speaking_samples {
+                            0 => None,
+                            samples => Some(power_db(speaking_power / samples as f32)),
+                        };
+                        let level_db = level_db
+                            .filter(|level_db| *level_db >= SPEAKING_THRESHOLD_DB)
+                            .map(f32::round);
+                        speaking_power = 0.0;
+                        speaking_samples = 0;
+                        if level_db != speaking_level {
+                            speaking_level = level_db;
+                            let _ = self
+                                .gui_commands_transmitter
+                                .try_send(ClientMessage::LocalSpeaking { level_db });
+                        }
+                    }
+                    // The capture callback wakes this task once a whole frame is buffered.
+                    _ = self.frame_ready.notified() => {
+                        if muted {
+                            self.consumer.clear();
+                            continue;
+                        }
+                        while self.consumer.occupied_len() >= self.samples_per_frame {
+                            self.consumer.pop_slice(&mut raw_samples);
+                            // The meter and VAD see the input gain but not the automatic
+                            // gain, so the threshold set against the meter keeps its meaning.
+                            self.gain_control.apply_input_gain(&mut raw_samples);
 
-                    match self.voice_input_control_receiver.try_recv() {
-                        Ok(message) => match message {
-                            VoiceMessage::CloseVoiceInput {} => break,
-                            _ => {}
-                        },
-                        Err(TryRecvError::Disconnected) => {
-                            println!("Voice input channel closed");
-                            break;
+                            if level_meter {
+                                for sample in &raw_samples {
+                                    level_peak = level_peak.max(sample.abs());
+                                    level_power += sample * sample;
+                                }
+                                level_frames += 1;
+                                if level_frames == LEVEL_REPORT_FRAMES {
+                                    let _ = self.gui_commands_transmitter.try_send(
+                                        ClientMessage::InputLevel {
+                                            peak_db: amplitude_db(level_peak),
+                                            rms_db: power_db(
+                                                level_power
+                                                    / (raw_samples.len() * LEVEL_REPORT_FRAMES)
+                                                        as f32,
+                                            ),
+                                        },
+                                    );
+                                    level_frames = 0;
+                                    level_peak = 0.0;
+                                    level_power = 0.0;
+                                }
+                            }
+
+                            let voice_detected = self.voice_activity_detector.process(&raw_samples);
+                            self.gain_control
+                                .apply_automatic_gain(&mut raw_samples, voice_detected);
+                            let transmitting = match self.transmit_mode {
+                                TransmitMode::Continuous => true,
+                                TransmitMode::VoiceActivity => voice_detected,
+                                TransmitMode::PushToTalk => {
+                                    push_to_talk_held
+                                        || push_to_talk_released_at.is_some_and(|released_at| {
+                                            released_at.elapsed() < PUSH_TO_TALK_RELEASE_TAIL
+                                        })
+                                }
+                            };
+                            if transmitting && voice_detected {
+                                speaking_power +=
+                                    raw_samples.iter().map(|sample| sample * sample).sum::<f32>();
+                                speaking_samples += raw_samples.len();
+                            }
+                            if !transmitting || matches!(self.sink, VoiceSink::Discard) {
+                                continue;
+                            }
+
+                            let opus_size = match self
+                                .encoder
+                                .encode_float(&raw_samples, &mut opus_output_buffer)
+                            {
+                                Ok(s) => s,
+                                Err(e) => {
+                                    eprintln!("Encode error: {:?}", e);
+                                    continue;
+                                }
+                            };
+                            if opus_size <= DTX_PACKET_MAX_LEN {
+                                continue;
+                            }
+
+                            let body = opus_output_buffer[0..opus_size].to_vec();
+                            let order_id = sequence_number;
+                            sequence_number = sequence_number.wrapping_add(1);
+
+                            match &self.sink {
+                                VoiceSink::Server(connection) => {
+                                    let packet = RoomMessage::VoicePacket {
+                                        body,
+                                        order_id,
+                                        user_id: u64::MAX,
+                                    };
+                                    match bincode::serialize(&packet) {
+                                        Ok(serialized_data) => {
+                                            match connection.send_datagram(serialized_data) {
+                                                Ok(_) => {}
+                                                Err(err) => {
+                                                    println!(
+                                                        "Error during sending voice datagram: {}",
+                                                        err
+                                                    );
+                                                    break 'run;
+                                                }
+                                            }
+                                        }
+                                        Err(err) => {
+                                            println!(
+                                                "Error during serializing voice datagram: {}",
+                                                err
+                                            );
+                                            break 'run;
+                                        }
+                                    }
+                                }
+                                VoiceSink::Loopback(packet_transmitter) => {
+                                    let _ = packet_transmitter.try_send((body, order_id));
+                                }
+                                VoiceSink::Discard => {}
+                            }
                         }
-                        _ => {}
                     }
-                } else {
-                    sleep(Duration::from_millis(1)).await;
                 }
             }
+            if speaking_level.is_some() {
+                let _ = self
+                    .gui_commands_transmitter
+                    .try_send(ClientMessage::LocalSpeaking { level_db: None });
+            }
         });
 
         Ok(())
     }
 }
+
+fn configure_encoder(
+    encoder: &mut OpusEncoder,
+    encoder_settings: &EncoderSettings,
+) -> Result<(), audiopus::Error> {
+    encoder.set_bitrate(Bitrate::BitsPerSecond(
+        encoder_settings.bitrate_kbps as i32 * 1000,
+    ))?;
+    encoder.set_complexity(encoder_settings.complexity)?;
+    encoder.set_inband_fec(encoder_settings.forward_error_correction)
+}
+
+fn adapt_encoder(
+    encoder: &mut OpusEncoder,
+    loss_percent: u8,
+    bitrate_kbps: u32,
+) -> Result<(), audiopus::Error> {
+    encoder.set_packet_loss_perc(loss_percent)?;
+    encoder.set_bitrate(Bitrate::BitsPerSecond(bitrate_kbps as i32 * 1000))
+}
+
+/// What the capture callback needs to hand frames over to the encoding task.
+struct Capture {
+    channel_mode: ChannelMode,
+    producer: HeapProd<f32>,
+    samples_per_frame: usize,
+    frame_ready: Arc<Notify>,
+}
+
+/// Captures at the device's native format and converts every frame to the 48 kHz pipeline
+/// format of the selected channel mode before it reaches the encoder.
+fn build_capture_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    capture: Capture,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let device_channels = config.channels as usize;
+    let mut resampler = Resampler::new(config.sample_rate, SAMPLE_RATE);
+    let Capture {
+        channel_mode,
+        mut producer,
+        samples_per_frame,
+        frame_ready,
+    } = capture;
+    device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            for device_frame in data.chunks_exact(device_channels) {
+                resampler.push(
+                    read_stereo_frame(device_frame),
+                    |frame| match channel_mode {
+                        ChannelMode::Mono => {
+                            let _ = producer.try_push((frame[0] + frame[1]) * 0.5);
+                        }
+                        // A partial frame would swap the channels of everything after it.
+                        ChannelMode::Stereo if producer.vacant_len() >= frame.len() => {
+                            producer.push_slice(&frame);
+                        }
+                        ChannelMode::Stereo => {}
+                    },
+                );
+            }
+            if producer.occupied_len() >= samples_per_frame {
+                frame_ready.notify_one();
+            }
+        },
+        move |err| eprintln!("Stream error: {}", err),
+        None,
+    )
+}
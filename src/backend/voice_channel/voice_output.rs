@@ -1,133 +1,357 @@
-use audiopus::{Channels, SampleRate, coder::Decoder as OpusDecoder};
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use audiopus::{Channels, SampleRate, coder::Decoder as OpusDecoder, packet};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample, StreamConfig};
 use ringbuf::HeapRb;
-use ringbuf::traits::{Consumer, Producer, Split};
+use ringbuf::traits::{Observer, Producer, Split};
+use tokio::select;
 use tokio::sync::mpsc::Receiver;
+use tokio::time::{Duration, Interval, MissedTickBehavior, interval};
 
-use std::collections::HashMap;
-use std::u64;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 
-use crossbeam_channel::unbounded;
-use ringbuf::{HeapCons, HeapProd};
+use ringbuf::HeapProd;
 
+use super::audio_devices::{output_device, output_stream_config};
+use super::jitter_buffer::{JitterBuffer, Playout};
+use super::mixer::{Mixer, MixerCommand, MixerHandle};
+use super::resampler::{Resampler, write_stereo_frame};
+use super::voice_activity::{SILENCE_DB, SPEAKING_THRESHOLD_DB, power_db};
 use crate::messages::voice_message::VoiceMessage;
 
-type AudioSource = HeapCons<f32>;
-
 const SAMPLE_RATE: u32 = 48000;
 const CHANNELS: usize = 2;
+const LOOPBACK_USER_ID: u64 = 0;
+const FRAME_SAMPLES_PER_CHANNEL: usize = 960;
+/// Decoded audio kept ahead of the output callback, two frames absorb the playout tick jitter.
+const PLAYOUT_PREFILL_SAMPLES: usize = 2 * FRAME_SAMPLES_PER_CHANNEL * CHANNELS;
+const PLAYOUT_INTERVAL: Duration = Duration::from_millis(10);
+/// How often the speaking levels are measured, the GUI only hears about changes.
+const ACTIVITY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Playback state of one room member.
+struct UserVoice {
+    producer: HeapProd<f32>,
+    decoder: OpusDecoder,
+    decoder_channels: Channels,
+    jitter_buffer: JitterBuffer,
+    /// Sum of squares and count of the samples decoded since the last activity update.
+    activity_power: f32,
+    activity_samples: usize,
+}
 
 pub struct VoiceOutput {
     _output_stream: cpal::Stream,
-    queue_sender: crossbeam_channel::Sender<AudioSource>,
-    user_sender: HashMap<u64, (HeapProd<f32>, OpusDecoder, u64, f32)>,
+    mixer: MixerHandle,
+    user_voices: HashMap<u64, UserVoice>,
+    /// Members who left the room. Their late or reordered datagrams are dropped instead of
+    /// bringing the member back.
+    departed_users: HashSet<u64>,
     voice_output_control_receiver: Receiver<VoiceMessage>,
+    published_activity: Vec<(u64, f32)>,
+    forward_error_correction: bool,
 }
 
 impl VoiceOutput {
     pub fn new(
         voice_output_control_receiver: Receiver<VoiceMessage>,
+        output_device_id: Option<&str>,
+        output_volume: f32,
+        forward_error_correction: bool,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let host = cpal::default_host();
-        let output_device = host
-            .default_output_device()
-            .expect("No output device found");
-
-        let config = cpal::StreamConfig {
-            channels: CHANNELS as u16,
-            sample_rate: SAMPLE_RATE,
-            buffer_size: cpal::BufferSize::Default,
-        };
+        let output_device = output_device(output_device_id)?;
 
-        let (tx, rx) = unbounded::<AudioSource>();
+        let supported_config = output_stream_config(&output_device, SAMPLE_RATE)?;
+        let config = supported_config.config();
 
-        let mut active_sources: Vec<AudioSource> = Vec::new();
+        let (mixer, mixer_handle) = Mixer::new(output_volume);
 
-        let output_data_fn = move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-            while let Ok(new_source) = rx.try_recv() {
-                active_sources.push(new_source);
+        let output_stream = match supported_config.sample_format() {
+            SampleFormat::F32 => build_playback_stream::<f32>(&output_device, &config, mixer),
+            SampleFormat::I16 => build_playback_stream::<i16>(&output_device, &config, mixer),
+            SampleFormat::U16 => build_playback_stream::<u16>(&output_device, &config, mixer),
+            SampleFormat::I32 => build_playback_stream::<i32>(&output_device, &config, mixer),
+            sample_format => {
+                return Err(format!("Unsupported output sample format {}", sample_format).into());
             }
-            data.fill(0.0);
-            for sample in data {
-                for consumer in active_sources.iter_mut() {
-                    *sample += consumer.try_pop().unwrap_or(0.0);
-                }
-                *sample = sample.clamp(-1.0, 1.0);
-            }
-        };
-
-        let output_stream =
-            output_device.build_output_stream(&config, output_data_fn, _err_fn, None)?;
+        }?;
         output_stream.play()?;
 
         Ok(Self {
             _output_stream: output_stream,
-            queue_sender: tx,
-            user_sender: HashMap::new(),
+            mixer: mixer_handle,
+            user_voices: HashMap::new(),
+            departed_users: HashSet::new(),
             voice_output_control_receiver,
+            published_activity: Vec::new(),
+            forward_error_correction,
         })
     }
 
-    pub fn accept_packet(self: &mut Self, body: Vec<u8>, order_id: u64, user_id: u64) -> u64 {
-        let mut added_new_user = u64::MAX;
-        if !self.user_sender.contains_key(&user_id) {
-            let ring_buffer_len = SAMPLE_RATE as usize * CHANNELS;
-            let ring = HeapRb::<f32>::new(ring_buffer_len);
-            let (producer, consumer) = ring.split();
-            let encoder = OpusDecoder::new(SampleRate::Hz48000, Channels::Stereo).unwrap();
-            let _ = self
-                .user_sender
-                .insert(user_id, (producer, encoder, 0u64, 1.0));
-            let _ = self.queue_sender.send(consumer);
-            added_new_user = user_id;
+    pub async fn next_control_message(&mut self) -> Option<VoiceMessage> {
+        self.voice_output_control_receiver.recv().await
+    }
+
+    pub fn process_control_message(&mut self, message: VoiceMessage) {
+        match message {
+            VoiceMessage::SetVoiceVolume { user_id, volume } => {
+                self.mixer.send(MixerCommand::SetGain {
+                    user_id,
+                    gain: volume,
+                });
+            }
+            VoiceMessage::SetVoiceMuted { user_id, muted } => {
+                self.mixer.send(MixerCommand::SetMuted { user_id, muted });
+            }
+            // Dropping the producer lets the mixer retire the user's source once it's played out.
+            VoiceMessage::RemoveUser { user_id } => {
+                self.user_voices.remove(&user_id);
+                self.departed_users.insert(user_id);
+            }
+            VoiceMessage::AddUser { user_id } => {
+                self.departed_users.remove(&user_id);
+            }
+            VoiceMessage::SetOutputVolume { volume } => {
+                self.mixer
+                    .send(MixerCommand::SetMasterGain { gain: volume });
+            }
+            VoiceMessage::SetDeafened { deafened } => {
+                self.mixer.send(MixerCommand::SetDeafened { deafened });
+            }
+            VoiceMessage::SetEncoderSettings { encoder_settings } => {
+                self.forward_error_correction = encoder_settings.forward_error_correction;
+                for user_voice in self.user_voices.values_mut() {
+                    user_voice
+                        .jitter_buffer
+                        .set_forward_error_correction(self.forward_error_correction);
+                }
+            }
+            _ => {}
         }
-        if self.user_sender.get_mut(&user_id).unwrap().2 > order_id {
+    }
+
+    /// Plays the packets of the mic test loopback until the voice input feeding it stops.
+    pub fn play_loopback(mut self, mut packet_receiver: Receiver<(Vec<u8>, u64)>) {
+        tokio::spawn(async move {
+            let mut playout_interval = playout_interval();
+            loop {
+                select! {
+                    packet = packet_receiver.recv() => match packet {
+                        Some((body, order_id)) => {
+                            self.accept_packet(body, order_id, LOOPBACK_USER_ID);
+                        }
+                        None => break,
+                    },
+                    Some(message) = self.voice_output_control_receiver.recv() => {
+                        self.process_control_message(message);
+                    }
+                    _ = playout_interval.tick() => self.play_out(),
+                }
+            }
+        });
+    }
+
+    /// Queues the packet in the sender's jitter buffer. Returns the user id if this is the
+    /// first packet from that user, `u64::MAX` otherwise.
+    pub fn accept_packet(&mut self, body: Vec<u8>, order_id: u64, user_id: u64) -> u64 {
+        let mut added_new_user = u64::MAX;
+        if self.departed_users.contains(&user_id) {
             return added_new_user;
         }
-        loop {
-            match self.voice_output_control_receiver.try_recv() {
-                Ok(message) => match message {
-                    VoiceMessage::SetVoiceVolume { user_id, volume } => {
-                        self.user_sender.get_mut(&user_id).unwrap().3 = volume;
+        let user_voice = match self.user_voices.entry(user_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let ring_buffer_len = SAMPLE_RATE as usize * CHANNELS;
+                let ring = HeapRb::<f32>::new(ring_buffer_len);
+                let (producer, consumer) = ring.split();
+                let decoder = match OpusDecoder::new(SampleRate::Hz48000, Channels::Stereo) {
+                    Ok(decoder) => decoder,
+                    Err(e) => {
+                        eprintln!("Decoder error: {:?}", e);
+                        return added_new_user;
                     }
-                    _ => {}
-                },
-                Err(_) => {
-                    break;
+                };
+                self.mixer
+                    .send(MixerCommand::AddSource { user_id, consumer });
+                added_new_user = user_id;
+                entry.insert(UserVoice {
+                    producer,
+                    decoder,
+                    decoder_channels: Channels::Stereo,
+                    jitter_buffer: JitterBuffer::new(self.forward_error_correction),
+                    activity_power: 0.0,
+                    activity_samples: 0,
+                })
+            }
+        };
+        user_voice.jitter_buffer.insert(order_id, body);
+        added_new_user
+    }
+
+    /// Moves frames from the jitter buffers into the playback ring buffers, keeping a small
+    /// prefill there so the output callback never runs dry between two calls.
+    pub fn play_out(&mut self) {
+        self.mixer.release_retired_sources();
+        for user_voice in self.user_voices.values_mut() {
+            while user_voice.producer.occupied_len() < PLAYOUT_PREFILL_SAMPLES {
+                match user_voice.jitter_buffer.pop() {
+                    Playout::Packet(body) => user_voice.decode(Some(&body)),
+                    Playout::Missing {
+                        following_packet: Some(following_packet),
+                    } => user_voice.recover(&following_packet),
+                    Playout::Missing {
+                        following_packet: None,
+                    } => user_voice.decode(None),
+                    Playout::Empty => break,
                 }
             }
         }
-        self.user_sender.get_mut(&user_id).unwrap().2 = order_id;
+    }
 
-        let mut output_buffer = [0.0f32; 5760];
+    /// Returns who is speaking and how loud since the last call, or `None` if that is what
+    /// was returned last time. Levels are rounded to whole dB so a steady voice stays quiet.
+    pub fn voice_activity(&mut self) -> Option<Vec<(u64, f32)>> {
+        let mut activity: Vec<(u64, f32)> = self
+            .user_voices
+            .iter_mut()
+            .filter_map(|(&user_id, user_voice)| {
+                let level_db = user_voice.take_level_db();
+                (level_db >= SPEAKING_THRESHOLD_DB).then_some((user_id, level_db.round()))
+            })
+            .collect();
+        activity.sort_by_key(|(user_id, _)| *user_id);
+        if activity == self.published_activity {
+            return None;
+        }
+        self.published_activity = activity.clone();
+        Some(activity)
+    }
+}
 
-        let samples_decoded = match self.user_sender.get_mut(&user_id).unwrap().1.decode_float(
-            Some(&body),
-            &mut output_buffer[..],
-            false,
-        ) {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("Decode error: {:?}", e);
-                return added_new_user;
-            }
+impl UserVoice {
+    fn take_level_db(&mut self) -> f32 {
+        let level_db = match self.activity_samples {
+            0 => SILENCE_DB,
+            samples => power_db(self.activity_power / samples as f32),
         };
-        let decoded_slice = &mut output_buffer[0..samples_decoded * CHANNELS];
-        let volume = self.user_sender.get_mut(&user_id).unwrap().3;
-        for sample in decoded_slice.iter_mut() {
-            *sample *= volume;
+        self.activity_power = 0.0;
+        self.activity_samples = 0;
+        level_db
+    }
+
+    /// Rebuilds a lost frame from the in-band FEC data of the packet following it. Falls back
+    /// to concealment when that packet switched channel count and can't feed this decoder.
+    fn recover(&mut self, following_packet: &[u8]) {
+        if packet::nb_channels(following_packet).ok() != Some(self.decoder_channels) {
+            self.decode(None);
+            return;
         }
-        let _ = self
-            .user_sender
-            .get_mut(&user_id)
-            .unwrap()
-            .0
-            .push_slice(decoded_slice);
+        self.decode_frame(Some(following_packet), true);
+    }
 
-        added_new_user
+    /// Decodes one packet, or conceals a lost one when `body` is `None`.
+    fn decode(&mut self, body: Option<&[u8]>) {
+        if let Some(body) = body {
+            // The TOC byte of every Opus packet says whether the peer encoded mono or stereo.
+            let packet_channels = match packet::nb_channels(body) {
+                Ok(channels) => channels,
+                Err(e) => {
+                    eprintln!("Malformed voice packet: {:?}", e);
+                    return;
+                }
+            };
+            if self.decoder_channels != packet_channels {
+                match OpusDecoder::new(SampleRate::Hz48000, packet_channels) {
+                    Ok(decoder) => {
+                        self.decoder = decoder;
+                        self.decoder_channels = packet_channels;
+                    }
+                    Err(e) => {
+                        eprintln!("Decoder error: {:?}", e);
+                        return;
+                    }
+                }
+            }
+        }
+        self.decode_frame(body, false);
+    }
+
+    fn decode_frame(&mut self, body: Option<&[u8]>, fec: bool) {
+        let channels = match self.decoder_channels {
+            Channels::Mono => 1,
+            _ => CHANNELS,
+        };
+
+        let mut output_buffer = [0.0f32; 5760];
+        // Concealment and FEC produce as many samples as the buffer holds, so give them one frame.
+        let output_len = match body {
+            Some(_) if !fec => output_buffer.len(),
+            _ => FRAME_SAMPLES_PER_CHANNEL * channels,
+        };
+        let samples_decoded =
+            match self
+                .decoder
+                .decode_float(body, &mut output_buffer[..output_len], fec)
+            {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!("Decode error: {:?}", e);
+                    return;
+                }
+            };
+
+        let decoded_slice = &output_buffer[0..samples_decoded * channels];
+        self.activity_power += decoded_slice
+            .iter()
+            .map(|sample| sample * sample)
+            .sum::<f32>();
+        self.activity_samples += decoded_slice.len();
+
+        if channels == 1 {
+            for &sample in decoded_slice {
+                let _ = self.producer.push_slice(&[sample; CHANNELS]);
+            }
+        } else {
+            let _ = self.producer.push_slice(decoded_slice);
+        }
     }
 }
 
+pub fn playout_interval() -> Interval {
+    let mut playout_interval = interval(PLAYOUT_INTERVAL);
+    playout_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    playout_interval
+}
+
+pub fn activity_interval() -> Interval {
+    let mut activity_interval = interval(ACTIVITY_INTERVAL);
+    activity_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    activity_interval
+}
+
+/// Plays the mix of every source and converts it from the 48 kHz stereo pipeline format to
+/// the device's native format.
+fn build_playback_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    mut mixer: Mixer,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let device_channels = config.channels as usize;
+    let mut resampler = Resampler::new(SAMPLE_RATE, config.sample_rate);
+
+    let output_data_fn = move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+        for device_frame in data.chunks_exact_mut(device_channels) {
+            let frame = resampler.pull(|| mixer.next_frame());
+            write_stereo_frame(frame, device_frame);
+        }
+    };
+
+    device.build_output_stream(config, output_data_fn, _err_fn, None)
+}
+
 fn _err_fn(err: cpal::StreamError) {
     eprintln!("Stream error: {}", err);
 }
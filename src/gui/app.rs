@@ -1,11 +1,52 @@
+use std::{collections::HashMap, time::Duration};
+
+use time::UtcOffset;
 use tokio::sync::mpsc::{Receiver, Sender};
 
-use crate::messages::client_message::ClientMessage;
+use crate::{
+    messages::{
+        audio_device::AudioHostDevices, client_error::ClientErrorKind,
+        client_message::ClientMessage, user_profile::UserProfile,
+    },
+    settings::{
+        certificate_mode::CertificateMode, client_settings::ClientSettings,
+        member_audio_settings::MemberAudioSettings,
+    },
+};
+
+use super::{chat_panel::ChatMessage, global_hotkey::GlobalHotkey, notifications::Notification};
 
 pub struct EguiYawperClient {
     pub host_name: String,
     pub host_password: String,
+    pub certificate_mode: CertificateMode,
+    pub pinned_fingerprints: String,
+    pub certificate_warning: Option<(String, String, String)>,
+    pub settings: ClientSettings,
+    pub display_name: String,
+    pub color: [u8; 3],
+    pub own_user_id: Option<u64>,
+    pub user_profiles: HashMap<u64, UserProfile>,
     pub connected_to_host: bool,
+    pub reconnecting: Option<(u32, Duration)>,
+    pub show_settings: bool,
+    pub audio_hosts: Vec<AudioHostDevices>,
+    pub capturing_push_to_talk_key: bool,
+    pub push_to_talk_held: bool,
+    pub voice_activity_threshold_db: f32,
+    pub input_gain: f32,
+    pub output_volume: f32,
+    pub bitrate_kbps: u32,
+    pub encoder_complexity: u8,
+    pub local_speaking_level: Option<f32>,
+    pub input_level: Option<(f32, f32)>,
+    pub mic_loopback: bool,
+    pub self_muted: bool,
+    pub self_deafened: bool,
+    pub member_voice_states: HashMap<u64, (bool, bool)>,
+    /// Members heard speaking and their level in dBFS, see `ClientMessage::VoiceActivity`.
+    pub speaking_levels: HashMap<u64, f32>,
+    pub global_hotkey: Option<GlobalHotkey>,
     pub create_room_show: Option<bool>,
     pub new_room_name: String,
     pub new_room_password: String,
@@ -13,9 +54,18 @@ pub struct EguiYawperClient {
     pub join_room_name: String,
     pub join_room_password: String,
     pub rooms: Vec<String>,
+    pub room_user_counts: HashMap<String, u32>,
     pub active_room: String,
     pub in_room: bool,
-    pub voice_channel_list: Vec<(u64, f32)>,
+    pub voice_channel_list: Vec<(u64, MemberAudioSettings)>,
+    pub chat_messages: Vec<ChatMessage>,
+    pub chat_draft: String,
+    pub chat_unread: usize,
+    pub chat_at_bottom: bool,
+    pub chat_scroll_to_bottom: bool,
+    pub window_title: String,
+    pub local_offset: UtcOffset,
+    pub notifications: Vec<Notification>,
     pub backend_commands_transmitter: Sender<ClientMessage>,
     pub gui_commands_receiver: Receiver<ClientMessage>,
 }
@@ -24,11 +74,50 @@ impl EguiYawperClient {
     pub fn new(
         backend_commands_transmitter: Sender<ClientMessage>,
         gui_commands_receiver: Receiver<ClientMessage>,
+        local_offset: UtcOffset,
     ) -> Self {
-        Self {
+        let (mut settings, settings_error) = match ClientSettings::load() {
+            Ok(settings) => (settings, None),
+            Err(err) => (ClientSettings::default(), Some(err.to_string())),
+        };
+        settings.ensure_client_id();
+        let _ = backend_commands_transmitter.try_send(ClientMessage::SetAudioDevices {
+            input_device_id: settings.input_device_id.clone(),
+            output_device_id: settings.output_device_id.clone(),
+        });
+        let _ = backend_commands_transmitter.try_send(ClientMessage::SetVoiceSettings {
+            voice_settings: settings.voice.clone(),
+        });
+        let mut client = Self {
             host_name: String::new(),
             host_password: String::new(),
+            certificate_mode: CertificateMode::default(),
+            pinned_fingerprints: String::new(),
+            certificate_warning: None,
+            display_name: settings.display_name.clone(),
+            color: settings.color,
+            voice_activity_threshold_db: settings.voice.voice_activity_threshold_db,
+            input_gain: settings.voice.input_gain,
+            output_volume: settings.voice.output_volume,
+            bitrate_kbps: settings.voice.encoder.bitrate_kbps,
+            encoder_complexity: settings.voice.encoder.complexity,
+            settings,
+            own_user_id: None,
+            user_profiles: HashMap::new(),
             connected_to_host: false,
+            reconnecting: None,
+            show_settings: false,
+            audio_hosts: Vec::new(),
+            capturing_push_to_talk_key: false,
+            push_to_talk_held: false,
+            local_speaking_level: None,
+            input_level: None,
+            mic_loopback: false,
+            self_muted: false,
+            self_deafened: false,
+            member_voice_states: HashMap::new(),
+            speaking_levels: HashMap::new(),
+            global_hotkey: None,
             create_room_show: None,
             new_room_name: String::new(),
             new_room_password: String::new(),
@@ -36,35 +125,310 @@ impl EguiYawperClient {
             join_room_name: String::new(),
             join_room_password: String::new(),
             rooms: Vec::new(),
+            room_user_counts: HashMap::new(),
             active_room: String::new(),
             in_room: false,
             voice_channel_list: Vec::new(),
+            chat_messages: Vec::new(),
+            chat_draft: String::new(),
+            chat_unread: 0,
+            chat_at_bottom: true,
+            chat_scroll_to_bottom: false,
+            window_title: "Yawper".to_string(),
+            local_offset,
+            notifications: Vec::new(),
             backend_commands_transmitter,
             gui_commands_receiver,
+        };
+        if let Some(message) = settings_error {
+            client
+                .notifications
+                .push(Notification::persistent(ClientErrorKind::Settings, message));
+        }
+        client.update_global_hotkey();
+        client
+    }
+
+    pub fn load_certificate_mode(&mut self) {
+        self.certificate_mode = self.settings.certificate_mode(&self.host_name);
+        self.pinned_fingerprints = match &self.certificate_mode {
+            CertificateMode::Pinned { fingerprints } => fingerprints.join("\n"),
+            _ => String::new(),
+        };
+    }
+
+    pub fn connect(&mut self) {
+        if self.display_name.trim().is_empty() {
+            self.notifications.push(Notification::new(
+                ClientErrorKind::Connection,
+                "Choose a display name first".to_string(),
+            ));
+            return;
+        }
+        if let CertificateMode::Pinned { fingerprints } = &mut self.certificate_mode {
+            *fingerprints = self
+                .pinned_fingerprints
+                .split([',', '\n'])
+                .map(|fingerprint| fingerprint.trim().to_string())
+                .filter(|fingerprint| !fingerprint.is_empty())
+                .collect();
+        }
+        self.settings.certificate_modes.insert(
+            self.host_name.trim().to_string(),
+            self.certificate_mode.clone(),
+        );
+        self.settings.display_name = self.display_name.trim().to_string();
+        self.settings.color = self.color;
+        self.save_settings();
+
+        let message = ClientMessage::ConnectToServer {
+            host_name: self.host_name.clone(),
+            host_password: self.host_password.clone(),
+            certificate_mode: self.certificate_mode.clone(),
+            client_id: self.settings.client_id,
+            display_name: self.display_name.clone(),
+            color: self.color,
+        };
+        let _ = self.backend_commands_transmitter.try_send(message);
+    }
+
+    pub fn send_self_voice_state(&mut self) {
+        let _ = self
+            .backend_commands_transmitter
+            .try_send(ClientMessage::SetSelfVoiceState {
+                muted: self.self_muted,
+                deafened: self.self_deafened,
+            });
+    }
+
+    pub fn save_settings(&mut self) {
+        if let Err(err) = self.settings.save() {
+            self.notifications.push(Notification::new(
+                ClientErrorKind::Settings,
+                err.to_string(),
+            ));
+        }
+    }
+
+    /// Adds a room member to the voice list with the volume and mute remembered for them.
+    /// Their voice can arrive before their profile, so an untouched entry picks up the
+    /// remembered settings once the profile is known.
+    fn add_voice_channel_member(&mut self, user_id: u64) {
+        let member_audio = match self.user_profiles.get(&user_id) {
+            Some(profile) => self.settings.member_audio(profile.client_id),
+            None => MemberAudioSettings::default(),
+        };
+        match self
+            .voice_channel_list
+            .iter_mut()
+            .find(|(member, _)| *member == user_id)
+        {
+            Some((_, listed)) if listed.is_default() => *listed = member_audio,
+            Some(_) => return,
+            None => self.voice_channel_list.push((user_id, member_audio)),
+        }
+        if !member_audio.is_default() {
+            self.send_member_audio(user_id, member_audio);
+        }
+    }
+
+    /// Applies a member's volume and mute locally and remembers them for the next time.
+    pub fn update_member_audio(&mut self, user_id: u64, member_audio: MemberAudioSettings) {
+        self.send_member_audio(user_id, member_audio);
+        if let Some(profile) = self.user_profiles.get(&user_id) {
+            self.settings
+                .set_member_audio(profile.client_id, member_audio);
+            self.save_settings();
+        }
+    }
+
+    fn send_member_audio(&self, user_id: u64, member_audio: MemberAudioSettings) {
+        for message in [
+            ClientMessage::SetVoiceVolume {
+                user_id,
+                volume: member_audio.volume,
+            },
+            ClientMessage::SetVoiceMuted {
+                user_id,
+                muted: member_audio.muted,
+            },
+        ] {
+            if let Err(err) = self.backend_commands_transmitter.try_send(message) {
+                println!("Error during sending user id volume: {}", err);
+            }
         }
     }
+
+    fn reset_connection_state(&mut self) {
+        self.connected_to_host = false;
+        self.own_user_id = None;
+        self.user_profiles.clear();
+        self.reconnecting = None;
+        self.create_room_show = Some(false);
+        self.new_room_name.clear();
+        self.new_room_password.clear();
+        self.join_room_show = Some(false);
+        self.join_room_name.clear();
+        self.join_room_password.clear();
+        self.rooms.clear();
+        self.room_user_counts.clear();
+        self.active_room.clear();
+        self.in_room = false;
+        self.local_speaking_level = None;
+        self.voice_channel_list.clear();
+        self.member_voice_states.clear();
+        self.speaking_levels.clear();
+        self.clear_chat();
+    }
 }
 
 impl eframe::App for EguiYawperClient {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         while let Ok(message) = self.gui_commands_receiver.try_recv() {
             match message {
-                ClientMessage::ConnectionIsActive {} => self.connected_to_host = true,
-                ClientMessage::RoomList { rooms } => self.rooms = rooms,
+                ClientMessage::ConnectionIsActive { user_id } => {
+                    self.connected_to_host = true;
+                    self.own_user_id = user_id;
+                    self.reconnecting = None;
+                }
+                ClientMessage::Reconnecting { attempt, retry_in } => {
+                    self.reconnecting = Some((attempt, retry_in));
+                    self.voice_channel_list.clear();
+                }
+                ClientMessage::Disconnected {} => self.reset_connection_state(),
+                ClientMessage::RoomList { rooms } => {
+                    self.room_user_counts
+                        .retain(|room_name, _| rooms.contains(room_name));
+                    self.rooms = rooms;
+                }
+                ClientMessage::RoomCreated { room_name } if !self.rooms.contains(&room_name) => {
+                    self.rooms.push(room_name);
+                }
+                ClientMessage::RoomDeleted { room_name } => {
+                    self.rooms.retain(|room| room != &room_name);
+                    self.room_user_counts.remove(&room_name);
+                }
+                ClientMessage::RoomUserJoined { room_name } => {
+                    *self.room_user_counts.entry(room_name).or_default() += 1;
+                }
+                ClientMessage::RoomUserLeft { room_name } => {
+                    if let Some(user_count) = self.room_user_counts.get_mut(&room_name) {
+                        *user_count = user_count.saturating_sub(1);
+                    }
+                }
+                ClientMessage::RoomUserCount {
+                    room_name,
+                    user_count,
+                } => {
+                    self.room_user_counts.insert(room_name, user_count);
+                }
                 ClientMessage::RoomJoined { room_name } => {
                     self.active_room = room_name;
                     self.in_room = true;
+                    self.voice_channel_list.clear();
+                    self.member_voice_states.clear();
+                    self.speaking_levels.clear();
+                    self.clear_chat();
                     self.join_room_name.clear();
                     self.join_room_password.clear();
                     self.join_room_show = Some(false);
                 }
-                ClientMessage::NewVoiceChannel { user_id } => {
-                    self.voice_channel_list.push((user_id, 1.0));
+                ClientMessage::RoomLeft {} => {
+                    self.active_room.clear();
+                    self.in_room = false;
+                    self.local_speaking_level = None;
+                    self.voice_channel_list.clear();
+                    self.member_voice_states.clear();
+                    self.speaking_levels.clear();
+                    self.clear_chat();
+                }
+                ClientMessage::RoomRoster { users } => {
+                    let previous_members: HashMap<u64, MemberAudioSettings> =
+                        self.voice_channel_list.drain(..).collect();
+                    let user_ids: Vec<u64> = users.iter().map(|profile| profile.user_id).collect();
+                    self.user_profiles = users
+                        .into_iter()
+                        .map(|profile| (profile.user_id, profile))
+                        .collect();
+                    for user_id in user_ids {
+                        match previous_members.get(&user_id) {
+                            Some(member_audio) if !member_audio.is_default() => {
+                                self.voice_channel_list.push((user_id, *member_audio))
+                            }
+                            _ => self.add_voice_channel_member(user_id),
+                        }
+                    }
+                }
+                ClientMessage::RoomMemberJoined { user } => {
+                    let user_id = user.user_id;
+                    self.user_profiles.insert(user_id, user);
+                    self.add_voice_channel_member(user_id);
+                }
+                ClientMessage::RoomMemberLeft { user_id } => {
+                    self.voice_channel_list
+                        .retain(|(member, _)| *member != user_id);
+                    self.user_profiles.remove(&user_id);
+                    self.member_voice_states.remove(&user_id);
+                }
+                ClientMessage::ChatMessage { user_id, body } => {
+                    self.push_chat_message(user_id, body, ctx);
+                }
+                // Voice from outside the roster is a straggler from someone who left. Servers
+                // that didn't introduce us send no roster, there voice is all we have.
+                ClientMessage::NewVoiceChannel { user_id }
+                    if self.own_user_id.is_none() || self.user_profiles.contains_key(&user_id) =>
+                {
+                    self.add_voice_channel_member(user_id);
+                }
+                ClientMessage::AudioDevices { hosts } => self.audio_hosts = hosts,
+                ClientMessage::LocalSpeaking { level_db } => self.local_speaking_level = level_db,
+                ClientMessage::VoiceActivity { levels } => {
+                    self.speaking_levels = levels.into_iter().collect();
+                }
+                ClientMessage::InputLevel { peak_db, rms_db } => {
+                    self.input_level = Some((peak_db, rms_db));
+                }
+                ClientMessage::MemberVoiceState {
+                    user_id,
+                    muted,
+                    deafened,
+                } => {
+                    self.member_voice_states.insert(user_id, (muted, deafened));
+                }
+                ClientMessage::CertificateTrusted {
+                    host_name,
+                    fingerprint,
+                } => {
+                    let certificate_mode = CertificateMode::TrustOnFirstUse {
+                        fingerprint: Some(fingerprint),
+                    };
+                    if host_name.trim() == self.host_name.trim() {
+                        self.certificate_mode = certificate_mode.clone();
+                    }
+                    self.settings
+                        .certificate_modes
+                        .insert(host_name.trim().to_string(), certificate_mode);
+                    self.save_settings();
+                }
+                ClientMessage::CertificateChanged {
+                    host_name,
+                    expected,
+                    found,
+                } => {
+                    self.certificate_warning = Some((host_name, expected, found));
+                }
+                ClientMessage::Error { kind, message } => {
+                    self.notifications.push(Notification::new(kind, message));
                 }
                 _ => {}
             }
         }
+        self.yawper_push_to_talk(ctx);
         self.yawper_left_panel(ctx);
         self.yawper_right_panel(ctx);
+        self.yawper_chat_panel(ctx);
+        self.yawper_settings_window(ctx);
+        self.yawper_certificate_dialog(ctx);
+        self.yawper_notifications(ctx);
     }
 }
@@ -0,0 +1,52 @@
+use crate::settings::certificate_mode::CertificateMode;
+
+use super::app::EguiYawperClient;
+
+impl EguiYawperClient {
+    pub fn yawper_certificate_dialog(&mut self, ctx: &egui::Context) {
+        let Some((host_name, expected, found)) = &self.certificate_warning else {
+            return;
+        };
+
+        let mut trust_new_certificate = false;
+        let mut close = false;
+        egui::Modal::new(egui::Id::new("certificate_warning_dialog")).show(ctx, |ui| {
+            ui.set_max_width(420.0);
+            ui.heading("Server certificate changed!");
+            ui.label(format!(
+                "The certificate presented by {} doesn't match the one trusted before. \
+                 Someone may be intercepting the connection, the password was not sent.",
+                host_name.trim()
+            ));
+            ui.separator();
+            ui.label("Trusted fingerprint:");
+            ui.monospace(expected);
+            ui.label("Presented fingerprint:");
+            ui.monospace(found);
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Cancel").clicked() {
+                    close = true;
+                }
+                if ui
+                    .button("Trust new certificate and connect")
+                    .on_hover_text("Only do this if the server operator replaced the certificate")
+                    .clicked()
+                {
+                    trust_new_certificate = true;
+                }
+            });
+        });
+
+        if trust_new_certificate {
+            let found = found.clone();
+            self.certificate_warning = None;
+            self.certificate_mode = CertificateMode::TrustOnFirstUse {
+                fingerprint: Some(found),
+            };
+            self.connect();
+        } else if close {
+            self.certificate_warning = None;
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use time::{OffsetDateTime, format_description::FormatItem, macros::format_description};
+
+use crate::messages::{client_message::ClientMessage, user_profile::UserProfile};
+
+use super::{
+    app::EguiYawperClient,
+    user_label::{display_name, user_color},
+};
+
+const CHAT_HISTORY_LIMIT: usize = 1000;
+/// Author of our own messages while the server hasn't told us our user id.
+const OWN_MESSAGE_USER_ID: u64 = u64::MAX;
+const TIMESTAMP_FORMAT: &[FormatItem<'static>] = format_description!("[hour]:[minute]");
+
+pub struct ChatMessage {
+    pub user_id: u64,
+    pub body: String,
+    pub received_at: OffsetDateTime,
+}
+
+impl EguiYawperClient {
+    pub fn yawper_chat_panel(&mut self, ctx: &egui::Context) {
+        let window_focused = ctx.input(|input| input.focused);
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if !self.in_room {
+                return;
+            }
+
+            ui.horizontal(|ui| {
+                ui.heading("Chat:");
+                if self.chat_unread > 0
+                    && ui
+                        .button(format!("{} unread", self.chat_unread))
+                        .on_hover_text("Jump to the newest message")
+                        .clicked()
+                {
+                    self.chat_scroll_to_bottom = true;
+                }
+            });
+            ui.separator();
+
+            let input_height = ui.spacing().interact_size.y * 2.0;
+            let scroll_output = egui::ScrollArea::vertical()
+                .auto_shrink([false, false])
+                .max_height(ui.available_height() - input_height)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for message in &self.chat_messages {
+                        ui.horizontal_wrapped(|ui| {
+                            let timestamp = message
+                                .received_at
+                                .format(TIMESTAMP_FORMAT)
+                                .unwrap_or_default();
+                            ui.weak(timestamp);
+                            ui.colored_label(
+                                user_color(&self.user_profiles, message.user_id),
+                                author_name(&self.user_profiles, message.user_id) + ":",
+                            );
+                            ui.add(egui::Label::new(&message.body).wrap());
+                        });
+                        // Labels can't hold links, so they are repeated clickable below the text.
+                        for link in message.body.split_whitespace().filter(|word| is_link(word)) {
+                            ui.indent(("chat_link", link), |ui| ui.hyperlink(link));
+                        }
+                    }
+                    if self.chat_scroll_to_bottom {
+                        ui.scroll_to_cursor(Some(egui::Align::BOTTOM));
+                        self.chat_scroll_to_bottom = false;
+                    }
+                });
+            let scrolled_to = scroll_output.state.offset.y + scroll_output.inner_rect.height();
+            self.chat_at_bottom = scrolled_to >= scroll_output.content_size.y - 1.0;
+            if self.chat_at_bottom && window_focused {
+                self.chat_unread = 0;
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut self.chat_draft)
+                        .hint_text("Message")
+                        .desired_width(ui.available_width() - 60.0),
+                );
+                let submitted =
+                    response.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+                if (ui.button("Send").clicked() || submitted) && !self.chat_draft.trim().is_empty()
+                {
+                    self.send_chat_message();
+                    response.request_focus();
+                }
+            });
+        });
+
+        let title = if self.chat_unread > 0 {
+            format!("({}) Yawper", self.chat_unread)
+        } else {
+            "Yawper".to_string()
+        };
+        if title != self.window_title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.window_title = title;
+        }
+    }
+
+    pub fn push_chat_message(&mut self, user_id: u64, body: String, ctx: &egui::Context) {
+        let received_at = OffsetDateTime::now_utc().to_offset(self.local_offset);
+        self.chat_messages.push(ChatMessage {
+            user_id,
+            body,
+            received_at,
+        });
+        if self.chat_messages.len() > CHAT_HISTORY_LIMIT {
+            self.chat_messages.remove(0);
+        }
+        let window_focused = ctx.input(|input| input.focused);
+        if !self.chat_at_bottom || !window_focused {
+            self.chat_unread += 1;
+        }
+    }
+
+    pub fn clear_chat(&mut self) {
+        self.chat_messages.clear();
+        self.chat_unread = 0;
+        self.chat_at_bottom = true;
+    }
+
+    fn send_chat_message(&mut self) {
+        let body = self.chat_draft.trim().to_string();
+        self.chat_draft.clear();
+        // The server relays messages to the other members only, so ours is added right away.
+        // Without an id from the server it's shown under `OWN_MESSAGE_USER_ID` as "you".
+        let received_at = OffsetDateTime::now_utc().to_offset(self.local_offset);
+        self.chat_messages.push(ChatMessage {
+            user_id: self.own_user_id.unwrap_or(OWN_MESSAGE_USER_ID),
+            body: body.clone(),
+            received_at,
+        });
+        if self.chat_messages.len() > CHAT_HISTORY_LIMIT {
+            self.chat_messages.remove(0);
+        }
+        self.chat_scroll_to_bottom = true;
+        let _ = self
+            .backend_commands_transmitter
+            .try_send(ClientMessage::SendChatMessage { body });
+    }
+}
+
+fn author_name(user_profiles: &HashMap<u64, UserProfile>, user_id: u64) -> String {
+    if user_id == OWN_MESSAGE_USER_ID {
+        "You".to_string()
+    } else {
+        display_name(user_profiles, user_id)
+    }
+}
+
+fn is_link(word: &str) -> bool {
+    word.starts_with("https://") || word.starts_with("http://")
+}
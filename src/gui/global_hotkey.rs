@@ -0,0 +1,126 @@
+use std::error::Error;
+
+use tokio::sync::mpsc::Sender;
+
+use crate::messages::client_message::ClientMessage;
+
+pub const GLOBAL_HOTKEY_SUPPORTED: bool = cfg!(feature = "global-hotkey");
+
+/// Watches the push-to-talk key system wide, also while the window isn't focused.
+/// The watcher thread stops when this value is dropped.
+pub struct GlobalHotkey {
+    pub key: egui::Key,
+    #[cfg(feature = "global-hotkey")]
+    stop: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+#[cfg(feature = "global-hotkey")]
+impl GlobalHotkey {
+    pub fn start(
+        key: egui::Key,
+        backend_commands_transmitter: Sender<ClientMessage>,
+    ) -> Result<Self, Box<dyn Error>> {
+        use std::{
+            sync::{
+                Arc,
+                atomic::{AtomicBool, Ordering},
+            },
+            thread,
+            time::Duration,
+        };
+
+        use x11rb::{connection::Connection, protocol::xproto::ConnectionExt};
+
+        const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+        let keysym = keysym(key).ok_or_else(|| format!("{} can't be used globally", key.name()))?;
+        let (connection, _) = x11rb::rust_connection::RustConnection::connect(None)?;
+        let min_keycode = connection.setup().min_keycode;
+        let max_keycode = connection.setup().max_keycode;
+        let mapping = connection
+            .get_keyboard_mapping(min_keycode, max_keycode - min_keycode + 1)?
+            .reply()?;
+        let keysyms_per_keycode = mapping.keysyms_per_keycode.max(1) as usize;
+        let keycode = mapping
+            .keysyms
+            .chunks(keysyms_per_keycode)
+            .position(|keysyms| keysyms.contains(&keysym))
+            .map(|index| min_keycode as usize + index)
+            .ok_or_else(|| format!("{} isn't on the keyboard layout", key.name()))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        thread::spawn(move || {
+            let mut held = false;
+            while !thread_stop.load(Ordering::Relaxed) {
+                let keys = match connection.query_keymap().map(|cookie| cookie.reply()) {
+                    Ok(Ok(reply)) => reply.keys,
+                    _ => {
+                        println!("Lost the connection to the X server, global hotkey stopped");
+                        break;
+                    }
+                };
+                let pressed = keys[keycode / 8] & (1 << (keycode % 8)) != 0;
+                if pressed != held {
+                    held = pressed;
+                    let _ = backend_commands_transmitter
+                        .try_send(ClientMessage::PushToTalk { pressed });
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+            if held {
+                let _ = backend_commands_transmitter
+                    .try_send(ClientMessage::PushToTalk { pressed: false });
+            }
+        });
+
+        Ok(Self { key, stop })
+    }
+}
+
+#[cfg(not(feature = "global-hotkey"))]
+impl GlobalHotkey {
+    pub fn start(
+        _key: egui::Key,
+        _backend_commands_transmitter: Sender<ClientMessage>,
+    ) -> Result<Self, Box<dyn Error>> {
+        Err("Built without global hotkey support".into())
+    }
+}
+
+#[cfg(feature = "global-hotkey")]
+impl Drop for GlobalHotkey {
+    fn drop(&mut self) {
+        self.stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+/// Maps an egui key to the X11 keysym of its unshifted symbol.
+#[cfg(feature = "global-hotkey")]
+fn keysym(key: egui::Key) -> Option<u32> {
+    use egui::Key;
+
+    let name = key.name();
+    if let [symbol] = name.as_bytes()
+        && symbol.is_ascii_alphanumeric()
+    {
+        return Some(symbol.to_ascii_lowercase() as u32);
+    }
+    if let Some(number) = name
+        .strip_prefix('F')
+        .and_then(|number| number.parse::<u32>().ok())
+    {
+        return (1..=35).contains(&number).then_some(0xffbe + number - 1);
+    }
+    match key {
+        Key::Space => Some(0x0020),
+        Key::Tab => Some(0xff09),
+        Key::Backtick => Some(0x0060),
+        Key::Insert => Some(0xff63),
+        Key::Home => Some(0xff50),
+        Key::End => Some(0xff57),
+        Key::PageUp => Some(0xff55),
+        Key::PageDown => Some(0xff56),
+        _ => None,
+    }
+}
@@ -1,24 +1,37 @@
-use crate::messages::client_message::ClientMessage;
+use crate::{messages::client_message::ClientMessage, settings::certificate_mode::CertificateMode};
 
 use super::app::EguiYawperClient;
 
 impl EguiYawperClient {
     pub fn yawper_left_panel(&mut self, ctx: &egui::Context) {
         egui::SidePanel::left("my_right_side_panel").show(ctx, |ui| {
+            if ui.button("Settings").clicked() {
+                self.open_settings();
+            }
             if !self.connected_to_host {
                 ui.heading("Server Login:");
-                ui.add(egui::TextEdit::singleline(&mut self.host_name).hint_text("Host"));
+                if ui
+                    .add(egui::TextEdit::singleline(&mut self.host_name).hint_text("Host"))
+                    .changed()
+                {
+                    self.load_certificate_mode();
+                }
                 ui.add(
                     egui::TextEdit::singleline(&mut self.host_password)
                         .hint_text("Password")
                         .password(true),
                 );
+                ui.horizontal(|ui| {
+                    ui.color_edit_button_srgb(&mut self.color)
+                        .on_hover_text("Name color");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.display_name)
+                            .hint_text("Display name"),
+                    );
+                });
+                self.certificate_mode_selector(ui);
                 if ui.button("Connect").clicked() {
-                    let message = ClientMessage::ConnectToServer {
-                        host_name: self.host_name.clone(),
-                        host_password: self.host_password.clone(),
-                    };
-                    let _ = self.backend_commands_transmitter.try_send(message);
+                    self.connect();
                 }
                 ui.separator();
             } else {
@@ -34,7 +47,54 @@ impl EguiYawperClient {
                         .password(true)
                         .interactive(false),
                 );
-                if ui.button("Disconnect").clicked() {}
+                if ui.button("Disconnect").clicked() {
+                    let _ = self
+                        .backend_commands_transmitter
+                        .try_send(ClientMessage::Disconnect {});
+                }
+                if let Some((attempt, retry_in)) = self.reconnecting {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label(format!(
+                            "Connection lost, reconnecting (attempt {}, retry in {}s)",
+                            attempt,
+                            retry_in.as_secs()
+                        ));
+                    });
+                }
+                ui.horizontal(|ui| {
+                    let mute_label = if self.self_muted || self.self_deafened {
+                        "Unmute"
+                    } else {
+                        "Mute"
+                    };
+                    if ui
+                        .selectable_label(self.self_muted || self.self_deafened, mute_label)
+                        .on_hover_text("Stop sending your microphone")
+                        .clicked()
+                    {
+                        if self.self_deafened {
+                            self.self_deafened = false;
+                            self.self_muted = false;
+                        } else {
+                            self.self_muted = !self.self_muted;
+                        }
+                        self.send_self_voice_state();
+                    }
+                    let deafen_label = if self.self_deafened {
+                        "Undeafen"
+                    } else {
+                        "Deafen"
+                    };
+                    if ui
+                        .selectable_label(self.self_deafened, deafen_label)
+                        .on_hover_text("Silence everyone and mute yourself")
+                        .clicked()
+                    {
+                        self.self_deafened = !self.self_deafened;
+                        self.send_self_voice_state();
+                    }
+                });
                 ui.separator();
 
                 let create_room_id = ui.make_persistent_id("create_room_header");
@@ -120,21 +180,32 @@ impl EguiYawperClient {
                                 .hint_text("Password")
                                 .password(true),
                         );
-                        if ui.button("Join").clicked() {
-                            if !self.join_room_name.is_empty()
-                                && self.rooms.contains(&self.join_room_name)
-                            {
-                                let message = ClientMessage::JoinRoom {
-                                    room_name: self.join_room_name.clone(),
-                                    room_password: self.join_room_password.clone(),
-                                };
-                                let _ = self.backend_commands_transmitter.try_send(message);
-                            }
+                        let join_label = if self.in_room { "Switch" } else { "Join" };
+                        if ui.button(join_label).clicked()
+                            && !self.join_room_name.is_empty()
+                            && self.join_room_name != self.active_room
+                            && self.rooms.contains(&self.join_room_name)
+                        {
+                            let message = ClientMessage::JoinRoom {
+                                room_name: self.join_room_name.clone(),
+                                room_password: self.join_room_password.clone(),
+                            };
+                            let _ = self.backend_commands_transmitter.try_send(message);
                         }
                     });
 
                 ui.separator();
                 ui.heading("Room List:");
+                if self.in_room {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("In room: {}", self.active_room));
+                        if ui.button("Leave Room").clicked() {
+                            let _ = self
+                                .backend_commands_transmitter
+                                .try_send(ClientMessage::LeaveRoom {});
+                        }
+                    });
+                }
                 egui::ScrollArea::vertical()
                     .auto_shrink([false, false])
                     .show(ui, |ui| {
@@ -144,6 +215,11 @@ impl EguiYawperClient {
                                 .selectable_label(
                                     current_room_joined,
                                     room.clone()
+                                        + &self
+                                            .room_user_counts
+                                            .get(room)
+                                            .map(|user_count| format!(" ({})", user_count))
+                                            .unwrap_or_default()
                                         + if current_room_joined {
                                             " - joined"
                                         } else {
@@ -151,11 +227,11 @@ impl EguiYawperClient {
                                         },
                                 )
                                 .clicked()
+                                && !current_room_joined
                             {
-                                if !self.in_room {
-                                    self.join_room_name = room.clone();
-                                    self.join_room_show = Some(true);
-                                }
+                                self.join_room_name = room.clone();
+                                self.join_room_password.clear();
+                                self.join_room_show = Some(true);
                             }
                         }
                     });
@@ -163,4 +239,53 @@ impl EguiYawperClient {
             }
         });
     }
+
+    fn certificate_mode_selector(&mut self, ui: &mut egui::Ui) {
+        let stored_mode = self.settings.certificate_mode(&self.host_name);
+        let trust_on_first_use = match stored_mode {
+            CertificateMode::TrustOnFirstUse { .. } => stored_mode,
+            _ => CertificateMode::default(),
+        };
+        let choices = [
+            CertificateMode::SystemRoots,
+            CertificateMode::Pinned {
+                fingerprints: Vec::new(),
+            },
+            trust_on_first_use,
+            CertificateMode::Insecure,
+        ];
+        egui::ComboBox::from_label("Certificate")
+            .selected_text(self.certificate_mode.label())
+            .show_ui(ui, |ui| {
+                for choice in choices {
+                    let selected = std::mem::discriminant(&choice)
+                        == std::mem::discriminant(&self.certificate_mode);
+                    let label = choice.label();
+                    if ui.selectable_label(selected, label).clicked() && !selected {
+                        self.certificate_mode = choice;
+                    }
+                }
+            });
+        match &self.certificate_mode {
+            CertificateMode::Pinned { .. } => {
+                ui.add(
+                    egui::TextEdit::multiline(&mut self.pinned_fingerprints)
+                        .hint_text("SHA-256 fingerprints, one per line")
+                        .desired_rows(2),
+                );
+            }
+            CertificateMode::TrustOnFirstUse {
+                fingerprint: Some(fingerprint),
+            } => {
+                ui.small(format!("Trusted: {}", fingerprint));
+            }
+            CertificateMode::Insecure => {
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    "The password can be captured by anyone on the network path.",
+                );
+            }
+            _ => {}
+        }
+    }
 }
@@ -1,3 +1,10 @@
 pub mod app;
+mod certificate_dialog;
+mod chat_panel;
+mod global_hotkey;
 mod left_panel;
+mod notifications;
+mod push_to_talk;
 mod right_panel;
+mod settings_window;
+mod user_label;
@@ -0,0 +1,80 @@
+use std::time::{Duration, Instant};
+
+use crate::messages::client_error::ClientErrorKind;
+
+use super::app::EguiYawperClient;
+
+const NOTIFICATION_LIFETIME: Duration = Duration::from_secs(8);
+
+pub struct Notification {
+    pub kind: ClientErrorKind,
+    pub message: String,
+    pub created_at: Instant,
+    /// Stays until dismissed instead of expiring after `NOTIFICATION_LIFETIME`.
+    pub persistent: bool,
+}
+
+impl Notification {
+    pub fn new(kind: ClientErrorKind, message: String) -> Self {
+        Self {
+            kind,
+            message,
+            created_at: Instant::now(),
+            persistent: false,
+        }
+    }
+
+    /// For problems the user must not miss, like forgotten server certificates.
+    pub fn persistent(kind: ClientErrorKind, message: String) -> Self {
+        Self {
+            persistent: true,
+            ..Self::new(kind, message)
+        }
+    }
+}
+
+impl EguiYawperClient {
+    pub fn yawper_notifications(&mut self, ctx: &egui::Context) {
+        self.notifications.retain(|notification| {
+            notification.persistent || notification.created_at.elapsed() < NOTIFICATION_LIFETIME
+        });
+        if self.notifications.is_empty() {
+            return;
+        }
+
+        let mut dismissed = None;
+        egui::Area::new(egui::Id::new("notifications_area"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-10.0, -10.0))
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                for (index, notification) in self.notifications.iter().enumerate() {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.set_max_width(300.0);
+                        ui.horizontal(|ui| {
+                            ui.colored_label(
+                                ui.visuals().error_fg_color,
+                                notification.kind.to_string(),
+                            );
+                            if ui.small_button("x").on_hover_text("Dismiss").clicked() {
+                                dismissed = Some(index);
+                            }
+                        });
+                        ui.label(&notification.message);
+                    });
+                }
+            });
+        if let Some(index) = dismissed {
+            self.notifications.remove(index);
+        }
+
+        if let Some(oldest) = self
+            .notifications
+            .iter()
+            .find(|notification| !notification.persistent)
+        {
+            ctx.request_repaint_after(
+                NOTIFICATION_LIFETIME.saturating_sub(oldest.created_at.elapsed()),
+            );
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use crate::{
+    messages::{client_error::ClientErrorKind, client_message::ClientMessage},
+    settings::voice_settings::TransmitMode,
+};
+
+use super::{app::EguiYawperClient, global_hotkey::GlobalHotkey, notifications::Notification};
+
+impl EguiYawperClient {
+    /// Tracks the push-to-talk key while the window is focused. egui forgets held keys when
+    /// the window loses focus, so the key is released then as well.
+    pub fn yawper_push_to_talk(&mut self, ctx: &egui::Context) {
+        let voice_settings = &self.settings.voice;
+        if voice_settings.transmit_mode != TransmitMode::PushToTalk || self.global_hotkey.is_some()
+        {
+            return;
+        }
+        let Some(key) = egui::Key::from_name(&voice_settings.push_to_talk_key) else {
+            return;
+        };
+        let held = !self.capturing_push_to_talk_key
+            && !ctx.wants_keyboard_input()
+            && ctx.input(|input| input.key_down(key));
+        if held != self.push_to_talk_held {
+            self.push_to_talk_held = held;
+            let _ = self
+                .backend_commands_transmitter
+                .try_send(ClientMessage::PushToTalk { pressed: held });
+        }
+    }
+
+    /// Starts or stops the system wide hotkey watcher to match the voice settings.
+    pub fn update_global_hotkey(&mut self) {
+        let voice_settings = &self.settings.voice;
+        let key = egui::Key::from_name(&voice_settings.push_to_talk_key);
+        let wanted = match key {
+            Some(key)
+                if voice_settings.transmit_mode == TransmitMode::PushToTalk
+                    && voice_settings.push_to_talk_global =>
+            {
+                key
+            }
+            _ => {
+                self.global_hotkey = None;
+                return;
+            }
+        };
+        if self
+            .global_hotkey
+            .as_ref()
+            .is_some_and(|global_hotkey| global_hotkey.key == wanted)
+        {
+            return;
+        }
+        self.global_hotkey = None;
+        match GlobalHotkey::start(wanted, self.backend_commands_transmitter.clone()) {
+            Ok(global_hotkey) => {
+                self.push_to_talk_held = false;
+                self.global_hotkey = Some(global_hotkey);
+            }
+            Err(err) => {
+                self.notifications.push(Notification::new(
+                    ClientErrorKind::Settings,
+                    format!("Global push-to-talk unavailable: {}", err),
+                ));
+            }
+        }
+    }
+}
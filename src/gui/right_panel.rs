@@ -1,63 +1,93 @@
-use crate::messages::client_message::ClientMessage;
-
-use super::app::EguiYawperClient;
+use super::{
+    app::EguiYawperClient,
+    user_label::{speaking_level_bar, user_label, voice_state_icons},
+};
 
 impl EguiYawperClient {
     pub fn yawper_right_panel(&mut self, ctx: &egui::Context) {
+        let mut member_audio_changes = Vec::new();
         egui::SidePanel::right("my_left_side_panel").show(ctx, |ui| {
             if self.in_room {
-                ui.heading("Room Voice Level:");
+                ui.heading("Room Members:");
                 egui::ScrollArea::vertical()
                     .auto_shrink([false, false])
                     .show(ui, |ui| {
-                        for (user_id, volume) in self.voice_channel_list.iter_mut() {
-                            ui.horizontal(|ui| {
-                                ui.label("User ".to_owned() + user_id.to_string().as_str() + ":");
-                                let response = ui.add(
-                                    egui::Slider::new(volume, 0.0..=4.0)
-                                        .text("Volume")
-                                        .custom_formatter(|n, _| {
-                                            format!("{}%", (n * 100.0) as i32)
-                                        }),
-                                );
+                        for (user_id, member_audio) in self.voice_channel_list.iter_mut() {
+                            let own = Some(*user_id) == self.own_user_id;
+                            let speaking_level = if own {
+                                self.local_speaking_level
+                            } else {
+                                self.speaking_levels.get(user_id).copied()
+                            };
+                            let stroke = match speaking_level {
+                                Some(_) => egui::Stroke::new(1.0, egui::Color32::GREEN),
+                                None => egui::Stroke::NONE,
+                            };
+                            egui::Frame::new()
+                                .stroke(stroke)
+                                .corner_radius(4)
+                                .inner_margin(2)
+                                .show(ui, |ui| {
+                                    ui.horizontal(|ui| {
+                                        user_label(
+                                            ui,
+                                            &self.user_profiles,
+                                            self.own_user_id,
+                                            *user_id,
+                                        );
+                                        speaking_level_bar(ui, speaking_level);
+                                        if own {
+                                            voice_state_icons(
+                                                ui,
+                                                self.self_muted,
+                                                self.self_deafened,
+                                            );
+                                            return;
+                                        }
+                                        let (muted, deafened) = self
+                                            .member_voice_states
+                                            .get(user_id)
+                                            .copied()
+                                            .unwrap_or_default();
+                                        voice_state_icons(ui, muted, deafened);
+                                        if ui
+                                            .selectable_label(member_audio.muted, "Mute")
+                                            .on_hover_text("Mute this user for yourself only")
+                                            .clicked()
+                                        {
+                                            member_audio.muted = !member_audio.muted;
+                                            member_audio_changes.push((*user_id, *member_audio));
+                                        }
+                                        let response = ui.add_enabled(
+                                            !member_audio.muted,
+                                            egui::Slider::new(&mut member_audio.volume, 0.0..=4.0)
+                                                .text("Volume")
+                                                .custom_formatter(|n, _| {
+                                                    format!("{}%", (n * 100.0) as i32)
+                                                }),
+                                        );
 
-                                if response.drag_stopped() {
-                                    match self.backend_commands_transmitter.try_send(
-                                        ClientMessage::SetVoiceVolume {
-                                            user_id: *user_id,
-                                            volume: *volume,
-                                        },
-                                    ) {
-                                        Ok(_) => {}
-                                        Err(err) => {
-                                            println!("Error during sending user id volume: {}", err)
+                                        if response.drag_stopped() {
+                                            member_audio_changes.push((*user_id, *member_audio));
                                         }
-                                    }
-                                }
 
-                                if *volume != 1.0 {
-                                    if ui.button("R").on_hover_text("Reset to 100%").clicked() {
-                                        *volume = 1.0;
-                                        match self.backend_commands_transmitter.try_send(
-                                            ClientMessage::SetVoiceVolume {
-                                                user_id: *user_id,
-                                                volume: *volume,
-                                            },
-                                        ) {
-                                            Ok(_) => {}
-                                            Err(err) => {
-                                                println!(
-                                                    "Error during sending user id volume: {}",
-                                                    err
-                                                )
-                                            }
+                                        if member_audio.volume != 1.0
+                                            && ui
+                                                .button("R")
+                                                .on_hover_text("Reset to 100%")
+                                                .clicked()
+                                        {
+                                            member_audio.volume = 1.0;
+                                            member_audio_changes.push((*user_id, *member_audio));
                                         }
-                                    }
-                                }
-                            });
+                                    });
+                                });
                         }
                     });
             }
         });
+        for (user_id, member_audio) in member_audio_changes {
+            self.update_member_audio(user_id, member_audio);
+        }
     }
 }
@@ -0,0 +1,338 @@
+use crate::{
+    messages::{audio_device::AudioDeviceInfo, client_message::ClientMessage},
+    settings::voice_settings::{ChannelMode, TransmitMode},
+};
+
+use super::{app::EguiYawperClient, global_hotkey::GLOBAL_HOTKEY_SUPPORTED};
+
+const LEVEL_METER_FLOOR_DB: f32 = -80.0;
+
+impl EguiYawperClient {
+    pub fn open_settings(&mut self) {
+        if !self.show_settings {
+            self.input_level = None;
+            let _ = self
+                .backend_commands_transmitter
+                .try_send(ClientMessage::SetLevelMeter { enabled: true });
+        }
+        self.show_settings = true;
+        let _ = self
+            .backend_commands_transmitter
+            .try_send(ClientMessage::ListAudioDevices {});
+    }
+
+    fn close_settings(&mut self) {
+        self.show_settings = false;
+        self.capturing_push_to_talk_key = false;
+        let _ = self
+            .backend_commands_transmitter
+            .try_send(ClientMessage::SetLevelMeter { enabled: false });
+        if self.mic_loopback {
+            self.mic_loopback = false;
+            let _ = self
+                .backend_commands_transmitter
+                .try_send(ClientMessage::SetMicLoopback { enabled: false });
+        }
+    }
+
+    pub fn yawper_settings_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_settings;
+        let mut input_device_id = self.settings.input_device_id.clone();
+        let mut output_device_id = self.settings.output_device_id.clone();
+        let mut voice_settings = self.settings.voice.clone();
+        let mut refresh = false;
+        let mut mic_loopback = self.mic_loopback;
+        egui::Window::new("Settings")
+            .open(&mut open)
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.heading("Audio devices");
+                let input_devices: Vec<(String, &AudioDeviceInfo)> = self
+                    .audio_hosts
+                    .iter()
+                    .flat_map(|host| {
+                        host.input_devices
+                            .iter()
+                            .map(|device| (host.host_name.clone(), device))
+                    })
+                    .collect();
+                let output_devices: Vec<(String, &AudioDeviceInfo)> = self
+                    .audio_hosts
+                    .iter()
+                    .flat_map(|host| {
+                        host.output_devices
+                            .iter()
+                            .map(|device| (host.host_name.clone(), device))
+                    })
+                    .collect();
+                device_selector(ui, "Input", &input_devices, &mut input_device_id);
+                device_selector(ui, "Output", &output_devices, &mut output_device_id);
+                if ui.button("Refresh").clicked() {
+                    refresh = true;
+                }
+                let input_gain_response = ui.add(
+                    egui::Slider::new(&mut self.input_gain, 0.0..=4.0)
+                        .text("Input gain")
+                        .custom_formatter(|n, _| format!("{}%", (n * 100.0) as i32)),
+                );
+                if input_gain_response.drag_stopped()
+                    || (input_gain_response.changed() && !input_gain_response.dragged())
+                {
+                    voice_settings.input_gain = self.input_gain;
+                }
+                ui.checkbox(
+                    &mut voice_settings.automatic_gain_control,
+                    "Automatic gain control",
+                )
+                .on_hover_text("Evens out your level on top of the input gain");
+                let output_volume_response = ui.add(
+                    egui::Slider::new(&mut self.output_volume, 0.0..=2.0)
+                        .text("Output volume")
+                        .custom_formatter(|n, _| format!("{}%", (n * 100.0) as i32)),
+                );
+                if output_volume_response.drag_stopped()
+                    || (output_volume_response.changed() && !output_volume_response.dragged())
+                {
+                    voice_settings.output_volume = self.output_volume;
+                }
+
+                ui.separator();
+                ui.heading("Microphone test");
+                match self.input_level {
+                    Some((peak_db, rms_db)) => {
+                        level_meter(ui, peak_db, rms_db, self.voice_activity_threshold_db)
+                    }
+                    None => {
+                        ui.label("No input, check the device or unmute yourself");
+                    }
+                }
+                ui.add_enabled(
+                    !self.in_room,
+                    egui::Checkbox::new(&mut mic_loopback, "Hear yourself"),
+                )
+                .on_hover_text("Plays your voice back exactly as others would hear it")
+                .on_disabled_hover_text("Leave the room to run the loopback test");
+
+                ui.separator();
+                ui.heading("Voice");
+                egui::ComboBox::from_label("Channels")
+                    .selected_text(voice_settings.channel_mode.label())
+                    .show_ui(ui, |ui| {
+                        for channel_mode in [ChannelMode::Mono, ChannelMode::Stereo] {
+                            ui.selectable_value(
+                                &mut voice_settings.channel_mode,
+                                channel_mode,
+                                channel_mode.label(),
+                            );
+                        }
+                    })
+                    .response
+                    .on_hover_text("Mono uses about half the upload bandwidth");
+                egui::ComboBox::from_label("Transmit")
+                    .selected_text(voice_settings.transmit_mode.label())
+                    .show_ui(ui, |ui| {
+                        for transmit_mode in [
+                            TransmitMode::VoiceActivity,
+                            TransmitMode::PushToTalk,
+                            TransmitMode::Continuous,
+                        ] {
+                            ui.selectable_value(
+                                &mut voice_settings.transmit_mode,
+                                transmit_mode,
+                                transmit_mode.label(),
+                            );
+                        }
+                    });
+                let threshold_response = ui
+                    .add(
+                        egui::Slider::new(&mut self.voice_activity_threshold_db, -80.0..=-10.0)
+                            .text("Voice activity threshold")
+                            .suffix(" dB"),
+                    )
+                    .on_hover_text("Input quieter than this counts as silence and isn't sent");
+                if threshold_response.changed() {
+                    let _ = self.backend_commands_transmitter.try_send(
+                        ClientMessage::SetVoiceActivityThreshold {
+                            threshold_db: self.voice_activity_threshold_db,
+                        },
+                    );
+                }
+                if threshold_response.drag_stopped()
+                    || (threshold_response.changed() && !threshold_response.dragged())
+                {
+                    voice_settings.voice_activity_threshold_db = self.voice_activity_threshold_db;
+                }
+                if voice_settings.transmit_mode == TransmitMode::PushToTalk {
+                    ui.horizontal(|ui| {
+                        ui.label("Push-to-talk key:");
+                        if self.capturing_push_to_talk_key {
+                            ui.label("press a key, Escape to cancel");
+                            let pressed_key = ui.input(|input| {
+                                input.events.iter().find_map(|event| match event {
+                                    egui::Event::Key {
+                                        key, pressed: true, ..
+                                    } => Some(*key),
+                                    _ => None,
+                                })
+                            });
+                            if let Some(key) = pressed_key {
+                                if key != egui::Key::Escape {
+                                    voice_settings.push_to_talk_key = key.name().to_string();
+                                }
+                                self.capturing_push_to_talk_key = false;
+                            }
+                        } else if ui.button(&voice_settings.push_to_talk_key).clicked() {
+                            self.capturing_push_to_talk_key = true;
+                        }
+                    });
+                    ui.add_enabled(
+                        GLOBAL_HOTKEY_SUPPORTED,
+                        egui::Checkbox::new(
+                            &mut voice_settings.push_to_talk_global,
+                            "Also when the window isn't focused",
+                        ),
+                    )
+                    .on_disabled_hover_text("Built without the global-hotkey feature");
+                }
+
+                ui.separator();
+                ui.heading("Encoder");
+                // Sliders only land in the settings once released, like the threshold above.
+                let bitrate_response = ui
+                    .add(
+                        egui::Slider::new(&mut self.bitrate_kbps, 8..=128)
+                            .text("Bitrate")
+                            .suffix(" kbps"),
+                    )
+                    .on_hover_text("Upper bound when the bitrate adapts to the connection");
+                if bitrate_response.drag_stopped()
+                    || (bitrate_response.changed() && !bitrate_response.dragged())
+                {
+                    voice_settings.encoder.bitrate_kbps = self.bitrate_kbps;
+                }
+                let complexity_response = ui
+                    .add(egui::Slider::new(&mut self.encoder_complexity, 0..=10).text("Complexity"))
+                    .on_hover_text("Higher sounds better and costs more CPU");
+                if complexity_response.drag_stopped()
+                    || (complexity_response.changed() && !complexity_response.dragged())
+                {
+                    voice_settings.encoder.complexity = self.encoder_complexity;
+                }
+                ui.checkbox(
+                    &mut voice_settings.encoder.forward_error_correction,
+                    "Forward error correction",
+                )
+                .on_hover_text("Spends some bitrate so others can recover single lost packets");
+                ui.checkbox(
+                    &mut voice_settings.encoder.adaptive_bitrate,
+                    "Adaptive bitrate",
+                )
+                .on_hover_text("Lowers the bitrate while the connection loses packets");
+            });
+        if !open {
+            self.close_settings();
+            return;
+        }
+        if mic_loopback != self.mic_loopback {
+            self.mic_loopback = mic_loopback;
+            let _ = self
+                .backend_commands_transmitter
+                .try_send(ClientMessage::SetMicLoopback {
+                    enabled: mic_loopback,
+                });
+        }
+        if refresh {
+            self.open_settings();
+        }
+        if input_device_id != self.settings.input_device_id
+            || output_device_id != self.settings.output_device_id
+        {
+            self.settings.input_device_id = input_device_id.clone();
+            self.settings.output_device_id = output_device_id.clone();
+            self.save_settings();
+            let _ = self
+                .backend_commands_transmitter
+                .try_send(ClientMessage::SetAudioDevices {
+                    input_device_id,
+                    output_device_id,
+                });
+        }
+        if voice_settings != self.settings.voice {
+            self.settings.voice = voice_settings.clone();
+            self.save_settings();
+            let _ = self
+                .backend_commands_transmitter
+                .try_send(ClientMessage::SetVoiceSettings { voice_settings });
+            self.update_global_hotkey();
+        }
+    }
+}
+
+/// Horizontal meter from -80 dBFS to 0 dBFS: RMS as a bar, peak as a line and the voice
+/// activity threshold as a marker.
+fn level_meter(ui: &mut egui::Ui, peak_db: f32, rms_db: f32, threshold_db: f32) {
+    let fraction = |db: f32| ((db - LEVEL_METER_FLOOR_DB) / -LEVEL_METER_FLOOR_DB).clamp(0.0, 1.0);
+    let (rect, _) = ui.allocate_exact_size(
+        egui::vec2(ui.available_width().min(320.0), 14.0),
+        egui::Sense::hover(),
+    );
+    let painter = ui.painter();
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+    let x = |db: f32| rect.left() + rect.width() * fraction(db);
+    let rms_color = if rms_db >= threshold_db {
+        egui::Color32::GREEN
+    } else {
+        egui::Color32::DARK_GREEN
+    };
+    painter.rect_filled(
+        egui::Rect::from_min_max(rect.min, egui::pos2(x(rms_db), rect.bottom())),
+        2.0,
+        rms_color,
+    );
+    let peak_color = if peak_db >= -1.0 {
+        egui::Color32::RED
+    } else {
+        ui.visuals().strong_text_color()
+    };
+    painter.vline(
+        x(peak_db),
+        rect.y_range(),
+        egui::Stroke::new(2.0, peak_color),
+    );
+    painter.vline(
+        x(threshold_db),
+        rect.y_range(),
+        egui::Stroke::new(1.0, ui.visuals().warn_fg_color),
+    );
+    ui.small(format!("Peak {:.0} dB, RMS {:.0} dB", peak_db, rms_db));
+}
+
+fn device_selector(
+    ui: &mut egui::Ui,
+    label: &str,
+    devices: &[(String, &AudioDeviceInfo)],
+    selected_id: &mut Option<String>,
+) {
+    let selected_text = match selected_id {
+        None => "System default".to_string(),
+        Some(id) => devices
+            .iter()
+            .find(|(_, device)| &device.id == id)
+            .map(|(host_name, device)| format!("{}: {}", host_name, device.name))
+            .unwrap_or_else(|| format!("{} (unavailable)", id)),
+    };
+    egui::ComboBox::from_label(label)
+        .selected_text(selected_text)
+        .width(280.0)
+        .show_ui(ui, |ui| {
+            ui.selectable_value(selected_id, None, "System default");
+            for (host_name, device) in devices {
+                ui.selectable_value(
+                    selected_id,
+                    Some(device.id.clone()),
+                    format!("{}: {}", host_name, device.name),
+                );
+            }
+        });
+}
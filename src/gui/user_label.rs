@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use crate::messages::user_profile::UserProfile;
+
+const AVATAR_SIZE: f32 = 18.0;
+/// Speaking levels mapped onto the level bar, in dBFS.
+const SPEAKING_LEVEL_FLOOR_DB: f32 = -60.0;
+const SPEAKING_LEVEL_CEILING_DB: f32 = -10.0;
+const SPEAKING_LEVEL_BAR_SIZE: egui::Vec2 = egui::vec2(40.0, 6.0);
+
+pub fn display_name(user_profiles: &HashMap<u64, UserProfile>, user_id: u64) -> String {
+    match user_profiles.get(&user_id) {
+        Some(profile) => profile.display_name.clone(),
+        None => format!("User {}", user_id),
+    }
+}
+
+pub fn user_color(user_profiles: &HashMap<u64, UserProfile>, user_id: u64) -> egui::Color32 {
+    match user_profiles.get(&user_id) {
+        Some(profile) => {
+            let [r, g, b] = profile.color;
+            egui::Color32::from_rgb(r, g, b)
+        }
+        None => egui::Color32::GRAY,
+    }
+}
+
+/// Shows whether a room member muted or deafened themselves.
+pub fn voice_state_icons(ui: &mut egui::Ui, muted: bool, deafened: bool) {
+    if deafened {
+        ui.label("🎧").on_hover_text("Deafened");
+    }
+    if muted || deafened {
+        ui.label("🔇").on_hover_text("Muted");
+    }
+}
+
+/// Draws a small level bar for a speaking user, `level_db` of `None` leaves the space empty
+/// so the row doesn't shift when someone starts talking.
+pub fn speaking_level_bar(ui: &mut egui::Ui, level_db: Option<f32>) {
+    let (rect, _) = ui.allocate_exact_size(SPEAKING_LEVEL_BAR_SIZE, egui::Sense::hover());
+    let Some(level_db) = level_db else {
+        return;
+    };
+    let fraction = ((level_db - SPEAKING_LEVEL_FLOOR_DB)
+        / (SPEAKING_LEVEL_CEILING_DB - SPEAKING_LEVEL_FLOOR_DB))
+        .clamp(0.1, 1.0);
+    let painter = ui.painter();
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+    let mut filled = rect;
+    filled.set_width(rect.width() * fraction);
+    painter.rect_filled(filled, 2.0, egui::Color32::GREEN);
+}
+
+/// Draws a colored initial avatar followed by the display name of the user.
+pub fn user_label(
+    ui: &mut egui::Ui,
+    user_profiles: &HashMap<u64, UserProfile>,
+    own_user_id: Option<u64>,
+    user_id: u64,
+) -> egui::Response {
+    let display_name = display_name(user_profiles, user_id);
+    let color = user_color(user_profiles, user_id);
+    ui.horizontal(|ui| {
+        let (rect, _) =
+            ui.allocate_exact_size(egui::vec2(AVATAR_SIZE, AVATAR_SIZE), egui::Sense::hover());
+        ui.painter()
+            .circle_filled(rect.center(), AVATAR_SIZE / 2.0, color);
+        let initial = display_name
+            .chars()
+            .next()
+            .map(|initial| initial.to_uppercase().to_string())
+            .unwrap_or_default();
+        ui.painter().text(
+            rect.center(),
+            egui::Align2::CENTER_CENTER,
+            initial,
+            egui::FontId::proportional(AVATAR_SIZE * 0.6),
+            egui::Color32::BLACK,
+        );
+
+        let label = if Some(user_id) == own_user_id {
+            format!("{} (you)", display_name)
+        } else {
+            display_name
+        };
+        ui.colored_label(color, label)
+    })
+    .inner
+}
@@ -1,22 +1,31 @@
 use backend::backend::BackendYawperClient;
 use gui::app::EguiYawperClient;
 use messages::client_message::ClientMessage;
-use tokio::sync::mpsc;
+use time::UtcOffset;
+use tokio::sync::{
+    mpsc::{self, Receiver, Sender},
+    oneshot,
+};
 
 mod backend;
 mod gui;
 mod messages;
+mod settings;
 
 fn main() -> Result<(), eframe::Error> {
+    // Must be queried before any other thread is spawned, see `UtcOffset::current_local_offset`.
+    let local_offset = UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC);
     let native_options = eframe::NativeOptions::default();
 
     let (backend_commands_transmitter, backend_commands_receiver) =
         mpsc::channel::<ClientMessage>(100);
 
     let (gui_commands_transmitter, gui_commands_receiver) = mpsc::channel::<ClientMessage>(100);
+    let (backend_events_transmitter, backend_events_receiver) = mpsc::channel::<ClientMessage>(100);
+    let (egui_context_transmitter, egui_context_receiver) = oneshot::channel::<egui::Context>();
 
     let mut yawper_backend =
-        BackendYawperClient::new(backend_commands_receiver, gui_commands_transmitter);
+        BackendYawperClient::new(backend_commands_receiver, backend_events_transmitter);
     std::thread::spawn(move || {
         let run_time = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
@@ -24,14 +33,50 @@ fn main() -> Result<(), eframe::Error> {
             .unwrap();
 
         run_time.block_on(async {
+            tokio::spawn(forward_to_gui(
+                backend_events_receiver,
+                gui_commands_transmitter,
+                egui_context_receiver,
+            ));
             yawper_backend.run().await;
         });
     });
 
-    let yawper_gui = EguiYawperClient::new(backend_commands_transmitter, gui_commands_receiver);
+    let yawper_gui = EguiYawperClient::new(
+        backend_commands_transmitter,
+        gui_commands_receiver,
+        local_offset,
+    );
     eframe::run_native(
         "Yawper",
         native_options,
-        Box::new(|_cc| Ok(Box::new(yawper_gui))),
+        Box::new(|cc| {
+            let _ = egui_context_transmitter.send(cc.egui_ctx.clone());
+            Ok(Box::new(yawper_gui))
+        }),
     )
 }
+
+async fn forward_to_gui(
+    mut backend_events_receiver: Receiver<ClientMessage>,
+    gui_commands_transmitter: Sender<ClientMessage>,
+    egui_context_receiver: oneshot::Receiver<egui::Context>,
+) {
+    let Ok(egui_context) = egui_context_receiver.await else {
+        return;
+    };
+    // egui only redraws on input, wake it so notifications show up right away.
+    while let Some(message) = backend_events_receiver.recv().await {
+        if gui_commands_transmitter.send(message).await.is_err() {
+            return;
+        }
+        // Messages that queued up meanwhile, mostly level meter and voice activity updates,
+        // share one repaint.
+        while let Ok(message) = backend_events_receiver.try_recv() {
+            if gui_commands_transmitter.send(message).await.is_err() {
+                return;
+            }
+        }
+        egui_context.request_repaint();
+    }
+}
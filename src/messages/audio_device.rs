@@ -0,0 +1,12 @@
+#[derive(Debug, Clone)]
+pub struct AudioDeviceInfo {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct AudioHostDevices {
+    pub host_name: String,
+    pub input_devices: Vec<AudioDeviceInfo>,
+    pub output_devices: Vec<AudioDeviceInfo>,
+}
@@ -0,0 +1,29 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientErrorKind {
+    Connection,
+    RoomCreation,
+    RoomJoin,
+    RoomLeave,
+    Chat,
+    VoiceInput,
+    VoiceOutput,
+    Settings,
+}
+
+impl fmt::Display for ClientErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let title = match self {
+            ClientErrorKind::Connection => "Connection failed",
+            ClientErrorKind::RoomCreation => "Room creation failed",
+            ClientErrorKind::RoomJoin => "Joining room failed",
+            ClientErrorKind::RoomLeave => "Leaving room failed",
+            ClientErrorKind::Chat => "Sending message failed",
+            ClientErrorKind::VoiceInput => "Microphone unavailable",
+            ClientErrorKind::VoiceOutput => "Speakers unavailable",
+            ClientErrorKind::Settings => "Settings problem",
+        };
+        write!(f, "{}", title)
+    }
+}
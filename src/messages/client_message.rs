@@ -1,8 +1,37 @@
+use std::time::Duration;
+
+use crate::settings::{certificate_mode::CertificateMode, voice_settings::VoiceSettings};
+
+use super::{
+    audio_device::AudioHostDevices, client_error::ClientErrorKind, user_profile::UserProfile,
+};
+
 pub enum ClientMessage {
-    ConnectionIsActive {},
+    ConnectionIsActive {
+        user_id: Option<u64>,
+    },
+    Reconnecting {
+        attempt: u32,
+        retry_in: Duration,
+    },
+    Disconnect {},
+    Disconnected {},
     ConnectToServer {
         host_name: String,
         host_password: String,
+        certificate_mode: CertificateMode,
+        client_id: u64,
+        display_name: String,
+        color: [u8; 3],
+    },
+    CertificateTrusted {
+        host_name: String,
+        fingerprint: String,
+    },
+    CertificateChanged {
+        host_name: String,
+        expected: String,
+        found: String,
     },
     CreateRoom {
         room_name: String,
@@ -15,14 +44,100 @@ pub enum ClientMessage {
     RoomJoined {
         room_name: String,
     },
+    LeaveRoom {},
+    RoomLeft {},
     RoomList {
         rooms: Vec<String>,
     },
+    RoomCreated {
+        room_name: String,
+    },
+    RoomDeleted {
+        room_name: String,
+    },
+    RoomUserJoined {
+        room_name: String,
+    },
+    RoomUserLeft {
+        room_name: String,
+    },
+    RoomUserCount {
+        room_name: String,
+        user_count: u32,
+    },
+    RoomRoster {
+        users: Vec<UserProfile>,
+    },
+    RoomMemberJoined {
+        user: UserProfile,
+    },
+    RoomMemberLeft {
+        user_id: u64,
+    },
+    SendChatMessage {
+        body: String,
+    },
+    ChatMessage {
+        user_id: u64,
+        body: String,
+    },
     NewVoiceChannel {
         user_id: u64,
     },
+    /// Room members heard speaking right now with their level in dBFS, sent on every change.
+    VoiceActivity {
+        levels: Vec<(u64, f32)>,
+    },
+    ListAudioDevices {},
+    AudioDevices {
+        hosts: Vec<AudioHostDevices>,
+    },
+    SetAudioDevices {
+        input_device_id: Option<String>,
+        output_device_id: Option<String>,
+    },
+    SetVoiceSettings {
+        voice_settings: VoiceSettings,
+    },
+    PushToTalk {
+        pressed: bool,
+    },
+    SetVoiceActivityThreshold {
+        threshold_db: f32,
+    },
+    /// Our own speaking level in dBFS while we are heard, `None` once we go quiet.
+    LocalSpeaking {
+        level_db: Option<f32>,
+    },
+    SetLevelMeter {
+        enabled: bool,
+    },
+    SetMicLoopback {
+        enabled: bool,
+    },
+    InputLevel {
+        peak_db: f32,
+        rms_db: f32,
+    },
+    SetSelfVoiceState {
+        muted: bool,
+        deafened: bool,
+    },
+    MemberVoiceState {
+        user_id: u64,
+        muted: bool,
+        deafened: bool,
+    },
     SetVoiceVolume {
         user_id: u64,
         volume: f32,
     },
+    SetVoiceMuted {
+        user_id: u64,
+        muted: bool,
+    },
+    Error {
+        kind: ClientErrorKind,
+        message: String,
+    },
 }
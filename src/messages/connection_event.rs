@@ -0,0 +1,9 @@
+use crate::backend::server_connection::ConnectionYawperClient;
+
+pub enum ConnectionEvent {
+    ConnectionLost {},
+    Reconnected { connection: ConnectionYawperClient },
+    ReconnectFailed { reason: String },
+    RoomMemberJoined { user_id: u64 },
+    RoomMemberLeft { user_id: u64 },
+}
@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+use super::{lobby_message::LobbyMessage, room_message::RoomMessage};
+
+/// Request id carried by frames the server sends on its own initiative.
+pub const PUSH_REQUEST_ID: u64 = 0;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ControlPayload {
+    Lobby(LobbyMessage),
+    Room(RoomMessage),
+}
+
+/// A single message on the control stream, sent as a big-endian `u32` length followed by the
+/// bincode encoded frame. Responses carry the request id of the frame they answer.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ControlFrame {
+    pub request_id: u64,
+    pub payload: ControlPayload,
+}
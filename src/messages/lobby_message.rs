@@ -3,9 +3,47 @@ use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize, Debug)]
 pub enum LobbyMessage {
     Empty {},
-    CreateRoom { room_name: String, password: String },
+    CreateRoom {
+        room_name: String,
+        password: String,
+    },
     ListRooms {},
-    ListRoomsResult { rooms: Vec<String> },
-    JoinRoom { room_name: String, password: String },
+    ListRoomsResult {
+        rooms: Vec<String>,
+    },
+    JoinRoom {
+        room_name: String,
+        password: String,
+    },
     ExitRoom {},
+    Subscribe {},
+    Subscribed {
+        rooms: Vec<(String, u32)>,
+    },
+    RoomCreated {
+        room_name: String,
+    },
+    RoomDeleted {
+        room_name: String,
+    },
+    UserJoinedRoom {
+        room_name: String,
+        user_id: u64,
+    },
+    UserLeftRoom {
+        room_name: String,
+        user_id: u64,
+    },
+    RoomUserCount {
+        room_name: String,
+        user_count: u32,
+    },
+    Introduce {
+        client_id: u64,
+        display_name: String,
+        color: [u8; 3],
+    },
+    Introduced {
+        user_id: u64,
+    },
 }
@@ -1,4 +1,9 @@
+pub mod audio_device;
+pub mod client_error;
 pub mod client_message;
+pub mod connection_event;
+pub mod control_frame;
 pub mod lobby_message;
 pub mod room_message;
+pub mod user_profile;
 pub mod voice_message;
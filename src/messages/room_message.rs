@@ -1,12 +1,18 @@
 use serde::{Deserialize, Serialize};
 
+use super::user_profile::UserProfile;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum RoomMessage {
     Empty {},
     Connected {},
     NotConnected {},
-    AcceptUser {},
-    RemoveUser {},
+    AcceptUser {
+        user: UserProfile,
+    },
+    RemoveUser {
+        user_id: u64,
+    },
     RoomEntered {},
     TxtMessage {
         body: String,
@@ -17,4 +23,12 @@ pub enum RoomMessage {
         order_id: u64,
         user_id: u64,
     },
+    Roster {
+        users: Vec<UserProfile>,
+    },
+    VoiceState {
+        user_id: u64,
+        muted: bool,
+        deafened: bool,
+    },
 }
@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UserProfile {
+    pub user_id: u64,
+    /// Persistent id the client introduced itself with.
+    pub client_id: u64,
+    pub display_name: String,
+    pub color: [u8; 3],
+}
@@ -1,4 +1,17 @@
+use crate::settings::voice_settings::EncoderSettings;
+
 pub enum VoiceMessage {
     CloseVoiceInput {},
     SetVoiceVolume { user_id: u64, volume: f32 },
+    SetVoiceMuted { user_id: u64, muted: bool },
+    AddUser { user_id: u64 },
+    RemoveUser { user_id: u64 },
+    PushToTalk { pressed: bool },
+    SetVoiceActivityThreshold { threshold_db: f32 },
+    SetMuted { muted: bool },
+    SetDeafened { deafened: bool },
+    SetLevelMeter { enabled: bool },
+    SetEncoderSettings { encoder_settings: EncoderSettings },
+    SetInputGain { gain: f32, automatic: bool },
+    SetOutputVolume { volume: f32 },
 }
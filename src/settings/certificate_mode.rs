@@ -0,0 +1,92 @@
+use std::{error::Error, fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use wtransport::tls::Sha256Digest;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum CertificateMode {
+    /// Validate the server certificate against the system root store.
+    SystemRoots,
+    /// Accept only certificates whose SHA-256 fingerprint is in the list.
+    Pinned { fingerprints: Vec<String> },
+    /// Remember the first fingerprint seen and refuse to connect if it changes.
+    TrustOnFirstUse { fingerprint: Option<String> },
+    /// Accept any certificate. Only for testing against trusted networks.
+    Insecure,
+}
+
+impl Default for CertificateMode {
+    fn default() -> Self {
+        CertificateMode::TrustOnFirstUse { fingerprint: None }
+    }
+}
+
+impl CertificateMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            CertificateMode::SystemRoots => "System roots",
+            CertificateMode::Pinned { .. } => "Pinned fingerprint",
+            CertificateMode::TrustOnFirstUse { .. } => "Trust on first use",
+            CertificateMode::Insecure => "Insecure (no validation)",
+        }
+    }
+
+    pub fn validates_chain(&self) -> bool {
+        matches!(self, CertificateMode::SystemRoots)
+    }
+
+    /// Checks the fingerprint of the certificate presented by the server.
+    /// Runs after the TLS handshake and before anything, including the password, is sent.
+    pub fn verify(&self, found: Option<&Sha256Digest>) -> Result<(), Box<dyn Error>> {
+        match self {
+            CertificateMode::SystemRoots | CertificateMode::Insecure => Ok(()),
+            CertificateMode::Pinned { fingerprints } => {
+                let found = found.ok_or("Server didn't present a certificate")?;
+                for fingerprint in fingerprints {
+                    let pinned = Sha256Digest::from_str(fingerprint.trim())
+                        .map_err(|_| format!("Invalid pinned fingerprint: {}", fingerprint))?;
+                    if &pinned == found {
+                        return Ok(());
+                    }
+                }
+                Err(format!(
+                    "Server certificate {} doesn't match any pinned fingerprint",
+                    found
+                )
+                .into())
+            }
+            CertificateMode::TrustOnFirstUse { fingerprint } => {
+                let found = found.ok_or("Server didn't present a certificate")?;
+                match fingerprint {
+                    Some(expected)
+                        if Sha256Digest::from_str(expected).ok().as_ref() != Some(found) =>
+                    {
+                        Err(Box::new(CertificateMismatch {
+                            expected: expected.clone(),
+                            found: found.to_string(),
+                        }))
+                    }
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CertificateMismatch {
+    pub expected: String,
+    pub found: String,
+}
+
+impl fmt::Display for CertificateMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Server certificate changed: expected {}, found {}",
+            self.expected, self.found
+        )
+    }
+}
+
+impl Error for CertificateMismatch {}
@@ -0,0 +1,152 @@
+use std::{
+    collections::HashMap,
+    env,
+    error::Error,
+    fs,
+    hash::{BuildHasher, RandomState},
+    io,
+    path::{Path, PathBuf},
+    process,
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    certificate_mode::CertificateMode,
+    member_audio_settings::{MemberAudioSettings, member_audio_key},
+    voice_settings::VoiceSettings,
+};
+
+const SETTINGS_DIRECTORY: &str = "yawper";
+const SETTINGS_FILE: &str = "settings.json";
+/// Unreadable settings are moved aside under this extension instead of being overwritten.
+const UNREADABLE_EXTENSION: &str = "unreadable";
+const DEFAULT_COLOR: [u8; 3] = [90, 170, 255];
+
+/// Stored as JSON, missing fields take their defaults so adding settings keeps old files valid.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ClientSettings {
+    /// Random id sent at login so others recognise this client across sessions. Zero until
+    /// `ensure_client_id` generates it.
+    pub client_id: u64,
+    pub certificate_modes: HashMap<String, CertificateMode>,
+    pub display_name: String,
+    pub color: [u8; 3],
+    pub input_device_id: Option<String>,
+    pub output_device_id: Option<String>,
+    pub voice: VoiceSettings,
+    /// Volume and mute of other users, see `member_audio_key`. Defaults aren't stored.
+    pub member_audio: HashMap<String, MemberAudioSettings>,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        Self {
+            client_id: 0,
+            certificate_modes: HashMap::new(),
+            display_name: String::new(),
+            color: DEFAULT_COLOR,
+            input_device_id: None,
+            output_device_id: None,
+            voice: VoiceSettings::default(),
+            member_audio: HashMap::new(),
+        }
+    }
+}
+
+impl ClientSettings {
+    /// Loads the settings from disk. A missing file yields the defaults. A file that can't be
+    /// parsed is moved aside so the next save doesn't destroy it, and the error says so: the
+    /// defaults know no server certificates, so the user has to be told.
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let Some(path) = settings_path() else {
+            return Ok(Self::default());
+        };
+        match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|err| set_aside(&path, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(format!("Couldn't read {}: {}", path.display(), err).into()),
+        }
+    }
+
+    /// Writes to a temporary file first, so a crash mid-write can't leave a truncated file.
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = settings_path().ok_or("No configuration directory available")?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temporary_path = path.with_extension("json.tmp");
+        fs::write(&temporary_path, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(temporary_path, path)?;
+        Ok(())
+    }
+
+    /// Generates the client id on first start, it's saved with the other settings on connect.
+    pub fn ensure_client_id(&mut self) {
+        if self.client_id != 0 {
+            return;
+        }
+        // RandomState is seeded from the OS, the time and pid only separate clients further.
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        self.client_id = RandomState::new().hash_one((now, process::id())).max(1);
+    }
+
+    pub fn certificate_mode(&self, host_name: &str) -> CertificateMode {
+        self.certificate_modes
+            .get(host_name.trim())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn member_audio(&self, client_id: u64) -> MemberAudioSettings {
+        self.member_audio
+            .get(&member_audio_key(client_id))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn set_member_audio(&mut self, client_id: u64, member_audio: MemberAudioSettings) {
+        let key = member_audio_key(client_id);
+        if member_audio.is_default() {
+            self.member_audio.remove(&key);
+        } else {
+            self.member_audio.insert(key, member_audio);
+        }
+    }
+}
+
+fn settings_path() -> Option<PathBuf> {
+    let config_directory = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(
+        config_directory
+            .join(SETTINGS_DIRECTORY)
+            .join(SETTINGS_FILE),
+    )
+}
+
+fn set_aside(path: &Path, err: impl std::fmt::Display) -> Box<dyn Error> {
+    let mut unreadable_path = path.as_os_str().to_owned();
+    unreadable_path.push(".");
+    unreadable_path.push(UNREADABLE_EXTENSION);
+    let unreadable_path = PathBuf::from(unreadable_path);
+    let kept = match fs::rename(path, &unreadable_path) {
+        Ok(_) => format!("The file was kept as {}.", unreadable_path.display()),
+        Err(_) => String::new(),
+    };
+    format!(
+        "Couldn't read the settings in {}: {}. {} Defaults are in use, so trusted and pinned \
+         server certificates are forgotten: check the fingerprint before you connect again.",
+        path.display(),
+        err,
+        kept
+    )
+    .into()
+}
@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+
+/// How another room member is played back locally.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct MemberAudioSettings {
+    pub volume: f32,
+    pub muted: bool,
+}
+
+impl Default for MemberAudioSettings {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            muted: false,
+        }
+    }
+}
+
+impl MemberAudioSettings {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// User ids only last for one connection, so members are remembered by the client id they
+/// introduce themselves with. JSON map keys are strings, hence the hex form.
+pub fn member_audio_key(client_id: u64) -> String {
+    format!("{:016x}", client_id)
+}
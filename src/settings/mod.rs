@@ -0,0 +1,4 @@
+pub mod certificate_mode;
+pub mod client_settings;
+pub mod member_audio_settings;
+pub mod voice_settings;
@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_PUSH_TO_TALK_KEY: &str = "V";
+const DEFAULT_VOICE_ACTIVITY_THRESHOLD_DB: f32 = -50.0;
+const DEFAULT_BITRATE_KBPS: u32 = 32;
+const DEFAULT_COMPLEXITY: u8 = 9;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelMode {
+    /// Capture and encode a single channel. Enough for a microphone and half the bandwidth.
+    #[default]
+    Mono,
+    /// Capture and encode both channels of the input device.
+    Stereo,
+}
+
+impl ChannelMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ChannelMode::Mono => "Mono",
+            ChannelMode::Stereo => "Stereo",
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        match self {
+            ChannelMode::Mono => 1,
+            ChannelMode::Stereo => 2,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransmitMode {
+    /// Send every captured frame while in a room.
+    Continuous,
+    /// Send only while the input level is above the voice activity threshold.
+    #[default]
+    VoiceActivity,
+    /// Send only while the push-to-talk key is held, plus a short tail after release.
+    PushToTalk,
+}
+
+impl TransmitMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            TransmitMode::Continuous => "Continuous",
+            TransmitMode::VoiceActivity => "Voice activity",
+            TransmitMode::PushToTalk => "Push to talk",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct EncoderSettings {
+    /// Target bitrate, the upper bound when the bitrate adapts to the network.
+    pub bitrate_kbps: u32,
+    /// Opus complexity from 0 to 10, trading CPU time for quality.
+    pub complexity: u8,
+    /// Embed a low bitrate copy of every frame in the next one, so single losses can be recovered.
+    pub forward_error_correction: bool,
+    /// Lower the bitrate while the connection loses packets or the round trip time is high.
+    pub adaptive_bitrate: bool,
+}
+
+impl Default for EncoderSettings {
+    fn default() -> Self {
+        Self {
+            bitrate_kbps: DEFAULT_BITRATE_KBPS,
+            complexity: DEFAULT_COMPLEXITY,
+            forward_error_correction: true,
+            adaptive_bitrate: true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct VoiceSettings {
+    pub channel_mode: ChannelMode,
+    pub transmit_mode: TransmitMode,
+    /// Name of the push-to-talk key as given by `egui::Key::name`.
+    pub push_to_talk_key: String,
+    /// Watch the key system wide instead of only while the window is focused.
+    pub push_to_talk_global: bool,
+    /// Frame level in dBFS above which the input counts as speech.
+    pub voice_activity_threshold_db: f32,
+    pub encoder: EncoderSettings,
+    /// Linear gain on the microphone before encoding.
+    pub input_gain: f32,
+    /// Steer the input towards a steady speech level on top of `input_gain`.
+    pub automatic_gain_control: bool,
+    /// Linear gain on the mix of everyone else.
+    pub output_volume: f32,
+}
+
+impl VoiceSettings {
+    /// Whether switching from `other` to these settings needs the microphone to be reopened.
+    /// Everything else is applied to the running voice input.
+    pub fn requires_restart(&self, other: &VoiceSettings) -> bool {
+        self.channel_mode != other.channel_mode || self.transmit_mode != other.transmit_mode
+    }
+}
+
+impl Default for VoiceSettings {
+    fn default() -> Self {
+        Self {
+            channel_mode: ChannelMode::default(),
+            transmit_mode: TransmitMode::default(),
+            push_to_talk_key: DEFAULT_PUSH_TO_TALK_KEY.to_string(),
+            push_to_talk_global: false,
+            voice_activity_threshold_db: DEFAULT_VOICE_ACTIVITY_THRESHOLD_DB,
+            encoder: EncoderSettings::default(),
+            input_gain: 1.0,
+            automatic_gain_control: false,
+            output_volume: 1.0,
+        }
+    }
+}